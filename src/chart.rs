@@ -0,0 +1,457 @@
+//! Charts are saved as a [`Chart`] in either RON or JSON.
+//!
+//! Sheet contents (automations, splines, bound sequences, repeaters, hit responses, ...) are
+//! each stored once in the list of their kind. Sheets refer to their content by index into
+//! that list, which is re-linked to an [`Instance`] of the spawned content entity on load.
+//! Spline lookup tables are not saved and are resampled on load.
+//!
+//! Hit layers are saved as lists of layer indices, which can be given names in `layers`.
+//!
+//! ```text
+//! (
+//!     version: 2,
+//!     tempo_map: [(offset: 0.5, bpm: 180.0, signature: (beats: 4, value: 4))],
+//!     layers: ["kick", "snare"],
+//!     automations: [[
+//!         (x: 0.0, val: 0.0, weight: Quadratic(0.0), out: Some(Sine(In))),
+//!         (x: 4.0, val: 1.0, weight: Sine(Out)),
+//!     ]],
+//!     colors: [(
+//!         upper: [(x: 0.0, val: (1.0, 1.0, 1.0, 1.0), weight: Constant)],
+//!         lower: [(x: 0.0, val: (0.0, 0.0, 0.0, 1.0), weight: Constant)],
+//!     )],
+//!     sheets: [
+//!         (position: (start: 0.0, duration: 4.0, coverage: (0, 3)), content: Automation(0)),
+//!         (position: (start: 0.0, duration: 4.0, coverage: (0, 3)), content: Color(0)),
+//!     ],
+//!     hit_prompts: [(
+//!         press_kind: Press(1.0),
+//!         press_strength: Single,
+//!         press_phat_key: false,
+//!         signal_layers: [0],
+//!     )],
+//! )
+//! ```
+//!
+//! Lists that are empty and sheets without repeater affinity can be omitted. Anchor lists of
+//! automations are the exception, they need at least one anchor and are sorted on load.
+//! The version is required, older versions are migrated to the current one on load.
+
+mod migration;
+
+use crate::{hit::*, latency::*, sheet::*, tempo::TempoMap};
+use std::collections::HashMap;
+
+use bevy::{
+    ecs::system::Command,
+    prelude::{Component, Entity, World},
+};
+use derive_more::{Display, From};
+use serde::{Deserialize, Serialize};
+
+pub const CHART_VERSION: u32 = 2;
+
+#[derive(Debug, Display, From)]
+pub enum ChartError {
+    Ron(ron::Error),
+    Json(serde_json::Error),
+    #[from(ignore)]
+    #[display(fmt = "Unsupported chart version {} (latest is {})", _0, CHART_VERSION)]
+    Version(u32),
+    #[from(ignore)]
+    #[display(fmt = "Sheet refers to missing content {:?}", _0)]
+    Dangling(Content),
+    #[from(ignore)]
+    #[display(
+        fmt = "Layer {} is out of range (layers must be below {})",
+        _0,
+        MAX_LAYERS
+    )]
+    Layer(u8),
+    #[from(ignore)]
+    #[display(
+        fmt = "Sheet covers channels {} to {}, which end before they start",
+        _0,
+        _1
+    )]
+    Coverage(u8, u8),
+}
+
+impl std::error::Error for ChartError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartFormat {
+    Ron,
+    Json,
+}
+
+/// Index into the chart list of the content's kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Content {
+    Automation(usize),
+    Spline(usize),
+    Color(usize),
+    Luminosity(usize),
+    Scale(usize),
+    Rotation(usize),
+    GeometryCtrl(usize),
+    Repeater(usize),
+    HitResponse(usize),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sheet {
+    pub position: SheetPosition,
+    #[serde(default = "no_affinity")]
+    pub repeater_affinity: RepeaterAffinity,
+    pub content: Content,
+}
+
+fn no_affinity() -> RepeaterAffinity {
+    RepeaterAffinity(false)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Chart {
+    pub version: u32,
+    pub tempo_map: TempoMap,
+    pub latency: LatencyOffsets,
+    /// Names of the hit layers, indexed by layer
+    pub layers: Vec<String>,
+    pub automations: Vec<Automation>,
+    pub splines: Vec<Spline>,
+    pub colors: Vec<Color>,
+    pub luminosities: Vec<Luminosity>,
+    pub scales: Vec<Scale>,
+    pub rotations: Vec<Rotation>,
+    pub geometry_ctrls: Vec<GeometryCtrl>,
+    pub repeaters: Vec<Repeater>,
+    pub hit_responses: Vec<HitResponse>,
+    pub sheets: Vec<Sheet>,
+    pub hit_prompts: Vec<HitPrompt>,
+}
+
+impl Default for Chart {
+    fn default() -> Self {
+        Self {
+            version: CHART_VERSION,
+            tempo_map: TempoMap::default(),
+            latency: LatencyOffsets::default(),
+            layers: vec![],
+            automations: vec![],
+            splines: vec![],
+            colors: vec![],
+            luminosities: vec![],
+            scales: vec![],
+            rotations: vec![],
+            geometry_ctrls: vec![],
+            repeaters: vec![],
+            hit_responses: vec![],
+            sheets: vec![],
+            hit_prompts: vec![],
+        }
+    }
+}
+
+fn spawn_all<T: Component>(world: &mut World, items: Vec<T>) -> Vec<Entity> {
+    items
+        .into_iter()
+        .map(|item| world.spawn().insert(item).id())
+        .collect()
+}
+
+fn collect_all<T: Component + Clone>(world: &mut World) -> (Vec<T>, HashMap<Entity, usize>) {
+    world
+        .query::<(Entity, &T)>()
+        .iter(world)
+        .enumerate()
+        .map(|(index, (entity, item))| (item.clone(), (entity, index)))
+        .unzip()
+}
+
+#[rustfmt::skip]
+fn collect_sheets<T: Component>(
+    world: &mut World,
+    indices: &HashMap<Entity, usize>,
+    content: fn(usize) -> Content,
+)
+    -> Vec<Sheet>
+{
+    world
+        .query::<(&SheetPosition, &RepeaterAffinity, &Instance<T>)>()
+        .iter(world)
+        .filter_map(|(position, repeater_affinity, instance)| indices
+            .get(&**instance)
+            .map(|index| Sheet {
+                position: *position,
+                repeater_affinity: *repeater_affinity,
+                content: content(*index),
+            })
+        )
+        .collect()
+}
+
+impl Chart {
+    pub fn load(source: &str, format: ChartFormat) -> Result<Self, ChartError> {
+        let chart = migration::migrate(source, format)?;
+        chart.validate()?;
+        Ok(chart)
+    }
+
+    pub fn save(&self, format: ChartFormat) -> Result<String, ChartError> {
+        Ok(match format {
+            ChartFormat::Ron => ron::ser::to_string_pretty(self, Default::default())?,
+            ChartFormat::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+
+    fn validate(&self) -> Result<(), ChartError> {
+        self.sheets
+            .iter()
+            .map(|sheet| sheet.position.coverage)
+            .find(|Coverage(first, last)| last < first)
+            .map_or(Ok(()), |Coverage(first, last)| {
+                Err(ChartError::Coverage(first, last))
+            })?;
+
+        self.sheets
+            .iter()
+            .map(|sheet| sheet.content)
+            .find(|content| {
+                let (index, len) = match *content {
+                    Content::Automation(index) => (index, self.automations.len()),
+                    Content::Spline(index) => (index, self.splines.len()),
+                    Content::Color(index) => (index, self.colors.len()),
+                    Content::Luminosity(index) => (index, self.luminosities.len()),
+                    Content::Scale(index) => (index, self.scales.len()),
+                    Content::Rotation(index) => (index, self.rotations.len()),
+                    Content::GeometryCtrl(index) => (index, self.geometry_ctrls.len()),
+                    Content::Repeater(index) => (index, self.repeaters.len()),
+                    Content::HitResponse(index) => (index, self.hit_responses.len()),
+                };
+                len <= index
+            })
+            .map_or(Ok(()), |content| Err(ChartError::Dangling(content)))
+    }
+
+    /// Spawns the content entities, then the sheets linked to them and the hit prompts
+    pub fn spawn(self, world: &mut World) {
+        world.insert_resource(self.tempo_map);
+        world.get_resource_or_insert_with(Latency::default).chart = self.latency;
+        world.insert_resource(LayerNames(self.layers));
+        let automations = spawn_all(world, self.automations);
+        let splines = spawn_all(
+            world,
+            self.splines
+                .into_iter()
+                .map(|mut spline| {
+                    spline.resample();
+                    spline
+                })
+                .collect(),
+        );
+        let colors = spawn_all(world, self.colors);
+        let luminosities = spawn_all(world, self.luminosities);
+        let scales = spawn_all(world, self.scales);
+        let rotations = spawn_all(world, self.rotations);
+        let geometry_ctrls = spawn_all(world, self.geometry_ctrls);
+        let repeaters = spawn_all(world, self.repeaters);
+        let hit_responses = spawn_all(world, self.hit_responses);
+
+        self.sheets.into_iter().for_each(|sheet| {
+            let mut entity = world.spawn();
+            entity.insert_bundle((sheet.position, sheet.repeater_affinity));
+
+            match sheet.content {
+                Content::Automation(index) => {
+                    entity.insert(Instance::<Automation>::new(automations[index]))
+                }
+                Content::Spline(index) => entity.insert(Instance::<Spline>::new(splines[index])),
+                Content::Color(index) => entity.insert(Instance::<Color>::new(colors[index])),
+                Content::Luminosity(index) => {
+                    entity.insert(Instance::<Luminosity>::new(luminosities[index]))
+                }
+                Content::Scale(index) => entity.insert(Instance::<Scale>::new(scales[index])),
+                Content::Rotation(index) => {
+                    entity.insert(Instance::<Rotation>::new(rotations[index]))
+                }
+                Content::GeometryCtrl(index) => {
+                    entity.insert(Instance::<GeometryCtrl>::new(geometry_ctrls[index]))
+                }
+                Content::Repeater(index) => {
+                    entity.insert(Instance::<Repeater>::new(repeaters[index]))
+                }
+                Content::HitResponse(index) => entity.insert_bundle((
+                    Instance::<HitResponse>::new(hit_responses[index]),
+                    ResponseState::Nil,
+                )),
+            };
+        });
+
+        spawn_all(world, self.hit_prompts);
+    }
+
+    /// Gathers every sheet and the content they refer to back into a chart
+    pub fn collect(world: &mut World) -> Self {
+        let (automations, automation_indices) = collect_all::<Automation>(world);
+        let (splines, spline_indices) = collect_all::<Spline>(world);
+        let (colors, color_indices) = collect_all::<Color>(world);
+        let (luminosities, luminosity_indices) = collect_all::<Luminosity>(world);
+        let (scales, scale_indices) = collect_all::<Scale>(world);
+        let (rotations, rotation_indices) = collect_all::<Rotation>(world);
+        let (geometry_ctrls, geometry_ctrl_indices) = collect_all::<GeometryCtrl>(world);
+        let (repeaters, repeater_indices) = collect_all::<Repeater>(world);
+        let (hit_responses, hit_response_indices) = collect_all::<HitResponse>(world);
+
+        let sheets = [
+            collect_sheets::<Automation>(world, &automation_indices, Content::Automation),
+            collect_sheets::<Spline>(world, &spline_indices, Content::Spline),
+            collect_sheets::<Color>(world, &color_indices, Content::Color),
+            collect_sheets::<Luminosity>(world, &luminosity_indices, Content::Luminosity),
+            collect_sheets::<Scale>(world, &scale_indices, Content::Scale),
+            collect_sheets::<Rotation>(world, &rotation_indices, Content::Rotation),
+            collect_sheets::<GeometryCtrl>(world, &geometry_ctrl_indices, Content::GeometryCtrl),
+            collect_sheets::<Repeater>(world, &repeater_indices, Content::Repeater),
+            collect_sheets::<HitResponse>(world, &hit_response_indices, Content::HitResponse),
+        ]
+        .concat();
+
+        Self {
+            version: CHART_VERSION,
+            tempo_map: world
+                .get_resource::<TempoMap>()
+                .cloned()
+                .unwrap_or_default(),
+            latency: world
+                .get_resource::<Latency>()
+                .map_or_else(Default::default, |latency| latency.chart),
+            layers: world
+                .get_resource::<LayerNames>()
+                .map_or_else(Vec::new, |names| names.0.clone()),
+            automations,
+            splines,
+            colors,
+            luminosities,
+            scales,
+            rotations,
+            geometry_ctrls,
+            repeaters,
+            hit_responses,
+            sheets,
+            hit_prompts: collect_all::<HitPrompt>(world).0,
+        }
+    }
+}
+
+impl Command for Chart {
+    fn write(self, world: &mut World) {
+        self.spawn(world)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHART: &str = r#"(
+        version: 2,
+        layers: ["kick", "snare"],
+        automations: [[
+            (x: 0.0, val: 0.0, weight: Quadratic(0.0), out: Some(Sine(In))),
+            (x: 4.0, val: 1.0, weight: Cubic(2.0)),
+        ]],
+        splines: [(
+            path: [
+                (curvature: Linear, position: (0.0, 100.0)),
+                (curvature: Circular((50.0, 150.0)), position: (100.0, 100.0)),
+                (curvature: Cubic((120.0, 0.0), (180.0, 200.0)), position: (200.0, 100.0)),
+            ],
+        )],
+        colors: [(
+            upper: [(x: 0.0, val: (1.0, 1.0, 1.0, 1.0), weight: Constant)],
+            lower: [(x: 0.0, val: (0.0, 0.0, 0.0, 1.0), weight: Constant)],
+        )],
+        geometry_ctrls: [(10.0, 10.0)],
+        repeaters: [(
+            ping_pong: true,
+            period: 1.0,
+            ceil: (start: 1.0, end: 0.5, weight: Quadratic(1.0)),
+            floor: (start: 0.0, end: 0.0, weight: Constant),
+        )],
+        hit_responses: [(kind: Toggle(4), layers: [1])],
+        sheets: [
+            (position: (start: 0.0, duration: 4.0, coverage: (0, 3)), content: Automation(0)),
+            (position: (start: 0.0, duration: 4.0, coverage: (4, 4)), content: Automation(0)),
+            (position: (start: 0.0, duration: 4.0, coverage: (0, 0)), content: Spline(0)),
+            (position: (start: 1.0, duration: 2.0, coverage: (1, 3)), content: Color(0)),
+            (
+                position: (start: 1.0, duration: 2.0, coverage: (1, 3)),
+                repeater_affinity: true,
+                content: GeometryCtrl(0),
+            ),
+            (position: (start: 0.0, duration: 4.0, coverage: (0, 3)), content: Repeater(0)),
+            (position: (start: 0.0, duration: 4.0, coverage: (0, 3)), content: HitResponse(0)),
+        ],
+        hit_prompts: [
+            (press_kind: Press(1.0), press_strength: Single, press_phat_key: false, signal_layers: [1]),
+            (press_kind: Hold(2.0, 3.0), press_strength: Double, press_phat_key: true, signal_layers: [0, 1]),
+        ],
+    )"#;
+
+    #[test]
+    fn chart_round_trip() {
+        let chart = Chart::load(CHART, ChartFormat::Ron).unwrap();
+        let saved = chart.save(ChartFormat::Ron).unwrap();
+
+        let json = chart.save(ChartFormat::Json).unwrap();
+        let from_json = Chart::load(&json, ChartFormat::Json).unwrap();
+        assert_eq!(from_json.save(ChartFormat::Ron).unwrap(), saved);
+
+        let mut world = World::new();
+        chart.spawn(&mut world);
+
+        assert_eq!(
+            world.resource::<LayerNames>().mask(&["kick", "snare"]),
+            Some(LayerMask(0b11))
+        );
+
+        assert_eq!(
+            world.query::<&Instance<Automation>>().iter(&world).count(),
+            2
+        );
+        assert!(world
+            .query::<&Spline>()
+            .iter(&world)
+            .all(|spline| !spline.lut.is_empty()));
+
+        assert_eq!(
+            Chart::collect(&mut world).save(ChartFormat::Ron).unwrap(),
+            saved
+        );
+    }
+
+    #[test]
+    fn chart_validation() {
+        assert!(matches!(
+            Chart::load("(version: 0)", ChartFormat::Ron),
+            Err(ChartError::Version(0))
+        ));
+
+        assert!(matches!(
+            Chart::load(
+                "(version: 1, sheets: [(position: (start: 0.0, duration: 1.0, coverage: (0, 0)), content: Scale(0))])",
+                ChartFormat::Ron
+            ),
+            Err(ChartError::Dangling(Content::Scale(0)))
+        ));
+
+        assert!(matches!(
+            Chart::load(
+                "(version: 2, sheets: [(position: (start: 0.0, duration: 1.0, coverage: (5, 2)), content: Scale(0))])",
+                ChartFormat::Ron
+            ),
+            Err(ChartError::Coverage(5, 2))
+        ));
+    }
+}
@@ -0,0 +1,210 @@
+//! Whenever a change to the sheet types changes how charts are saved, `CHART_VERSION` is bumped
+//! and a `v<N>` module is added here with a [`Migrate`] impl that converts the previous version
+//! into the version after. Migrations then run one step at a time until they reach the current
+//! [`Chart`]. Only the types whose fields changed are frozen into a `v<N>` module, the rest are
+//! shared with the current chart, so changing a shared type also changes how older versions
+//! load without any compile error. The fixtures in `fixtures/charts` are what guard against
+//! that, so every version should have one to keep the back catalog of charts loading.
+
+use super::*;
+
+use serde::de::DeserializeOwned;
+
+pub trait Migrate: DeserializeOwned {
+    fn migrate(self) -> Result<Chart, ChartError>;
+}
+
+impl Migrate for Chart {
+    fn migrate(self) -> Result<Chart, ChartError> {
+        Ok(self)
+    }
+}
+
+/// Hit layers were single indices instead of masks. Fields that were added with defaults before
+/// the version was bumped, like response conditions and latency, are kept so v1 charts saved
+/// with them still load.
+mod v1 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct HitPrompt {
+        press_kind: PressKind,
+        press_strength: PressStrength,
+        press_phat_key: bool,
+        signal_layer: u8,
+    }
+
+    #[derive(Deserialize)]
+    pub struct HitResponse {
+        kind: ResponseKind,
+        layer: u8,
+        #[serde(default)]
+        condition: ResponseCondition,
+    }
+
+    #[derive(Default, Deserialize)]
+    #[serde(default)]
+    pub struct Chart {
+        tempo_map: TempoMap,
+        latency: LatencyOffsets,
+        automations: Vec<Automation>,
+        splines: Vec<Spline>,
+        colors: Vec<Color>,
+        luminosities: Vec<Luminosity>,
+        scales: Vec<Scale>,
+        rotations: Vec<Rotation>,
+        geometry_ctrls: Vec<GeometryCtrl>,
+        repeaters: Vec<Repeater>,
+        hit_responses: Vec<HitResponse>,
+        sheets: Vec<Sheet>,
+        hit_prompts: Vec<HitPrompt>,
+    }
+
+    fn mask(layer: u8) -> Result<LayerMask, ChartError> {
+        LayerMask::checked(layer).ok_or(ChartError::Layer(layer))
+    }
+
+    impl Migrate for Chart {
+        fn migrate(self) -> Result<super::Chart, ChartError> {
+            let hit_responses = self
+                .hit_responses
+                .into_iter()
+                .map(|response| {
+                    Ok(super::HitResponse {
+                        kind: response.kind,
+                        layers: mask(response.layer)?,
+                        condition: response.condition,
+                    })
+                })
+                .collect::<Result<_, ChartError>>()?;
+
+            let hit_prompts = self
+                .hit_prompts
+                .into_iter()
+                .map(|prompt| {
+                    Ok(super::HitPrompt {
+                        press_kind: prompt.press_kind,
+                        press_strength: prompt.press_strength,
+                        press_phat_key: prompt.press_phat_key,
+                        signal_layers: mask(prompt.signal_layer)?,
+                    })
+                })
+                .collect::<Result<_, ChartError>>()?;
+
+            super::Chart {
+                version: 2,
+                tempo_map: self.tempo_map,
+                latency: self.latency,
+                layers: vec![],
+                automations: self.automations,
+                splines: self.splines,
+                colors: self.colors,
+                luminosities: self.luminosities,
+                scales: self.scales,
+                rotations: self.rotations,
+                geometry_ctrls: self.geometry_ctrls,
+                repeaters: self.repeaters,
+                hit_responses,
+                sheets: self.sheets,
+                hit_prompts,
+            }
+            .migrate()
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(source: &str, format: ChartFormat) -> Result<T, ChartError> {
+    Ok(match format {
+        ChartFormat::Ron => ron::from_str(source)?,
+        ChartFormat::Json => serde_json::from_str(source)?,
+    })
+}
+
+fn load_as<T: Migrate>(source: &str, format: ChartFormat) -> Result<Chart, ChartError> {
+    parse::<T>(source, format)?.migrate()
+}
+
+pub fn migrate(source: &str, format: ChartFormat) -> Result<Chart, ChartError> {
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+
+    match parse::<Version>(source, format)?.version {
+        1 => load_as::<v1::Chart>(source, format),
+        CHART_VERSION => load_as::<Chart>(source, format),
+        version => Err(ChartError::Version(version)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn back_catalog() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/charts");
+
+        fs::read_dir(fixtures)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .for_each(|path| {
+                let format = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("ron") => ChartFormat::Ron,
+                    Some("json") => ChartFormat::Json,
+                    _ => return,
+                };
+
+                let source = fs::read_to_string(&path).unwrap();
+                let chart = Chart::load(&source, format)
+                    .unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+                let saved = chart.save(format).unwrap();
+
+                assert_eq!(chart.version, CHART_VERSION);
+                assert_eq!(
+                    Chart::load(&saved, format).unwrap().save(format).unwrap(),
+                    saved
+                );
+            })
+    }
+
+    #[test]
+    fn unknown_versions() {
+        assert!(matches!(
+            Chart::load("()", ChartFormat::Ron),
+            Err(ChartError::Ron(_))
+        ));
+
+        assert!(matches!(
+            Chart::load(r#"{ "version": 0 }"#, ChartFormat::Json),
+            Err(ChartError::Version(0))
+        ));
+
+        assert!(matches!(
+            Chart::load(
+                &format!("(version: {})", CHART_VERSION + 1),
+                ChartFormat::Ron
+            ),
+            Err(ChartError::Version(_))
+        ));
+    }
+
+    #[test]
+    fn v1_layers() {
+        let prompt = |layer| {
+            format!(
+                "(version: 1, hit_prompts: [(press_kind: Press(1.0), press_strength: Single, \
+                 press_phat_key: false, signal_layer: {layer})])"
+            )
+        };
+
+        let chart = Chart::load(&prompt(5), ChartFormat::Ron).unwrap();
+        assert_eq!(chart.hit_prompts[0].signal_layers, LayerMask::layer(5));
+
+        assert!(matches!(
+            Chart::load(&prompt(70), ChartFormat::Ron),
+            Err(ChartError::Layer(70))
+        ));
+    }
+}
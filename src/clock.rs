@@ -0,0 +1,220 @@
+use crate::{utils::*, SongTime};
+
+use bevy::prelude::*;
+
+/// Drift beyond this many seconds is snapped to instead of corrected gradually
+pub const DRIFT_SNAP: f32 = 0.1;
+/// Portion of the drift against the audio position corrected per update
+pub const DRIFT_CORRECTION: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockBackend {
+    /// Follows the playback position reported in [`AudioPosition`] and advances with
+    /// Bevy's `Time` between reports
+    Audio,
+    /// Advances by a fixed step of song time every update, for deterministic headless runs
+    Fixed(P32),
+}
+
+/// Playback position of the song as last reported by the audio backend playing it.
+/// Reports are consumed by the clock so each should only be written once.
+///
+/// After a [`SongClock::seek`] the audio backend should take the seek with
+/// [`SongClock::take_seek`] and seek the audio to it. Reports are ignored until one lands within
+/// [`DRIFT_SNAP`] of the clock, so reports from before the audio followed the seek don't pull
+/// the clock back.
+#[derive(Default, Deref, DerefMut)]
+pub struct AudioPosition(pub Option<P32>);
+
+pub struct SongClock {
+    backend: ClockBackend,
+    position: P32,
+    rate: P32,
+    playing: bool,
+    pending_seek: Option<P32>,
+}
+
+impl Default for SongClock {
+    fn default() -> Self {
+        Self::new(ClockBackend::Audio)
+    }
+}
+
+impl SongClock {
+    pub fn new(backend: ClockBackend) -> Self {
+        Self {
+            backend,
+            position: p32(0.),
+            rate: p32(1.),
+            playing: false,
+            pending_seek: None,
+        }
+    }
+
+    pub fn fixed(step: f32) -> Self {
+        Self::new(ClockBackend::Fixed(p32(step)))
+    }
+
+    pub fn backend(&self) -> ClockBackend {
+        self.backend
+    }
+
+    pub fn position(&self) -> P32 {
+        self.position
+    }
+
+    pub fn rate(&self) -> P32 {
+        self.rate
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn set_rate(&mut self, rate: P32) {
+        self.rate = rate;
+    }
+
+    /// Audio reports are ignored until one lands near the seek, see [`AudioPosition`]
+    pub fn seek(&mut self, to: P32) {
+        self.position = to;
+        self.pending_seek = Some(to);
+    }
+
+    /// For the audio backend to follow seeks
+    pub fn take_seek(&mut self) -> Option<P32> {
+        self.pending_seek.take()
+    }
+
+    /// Whether reports are still being ignored after a seek
+    pub fn is_seeking(&self) -> bool {
+        self.pending_seek.is_some()
+    }
+
+    /// Never moves backwards unless the drift is snapped to
+    pub fn advance(&mut self, delta: f32, reported: Option<P32>) {
+        if !self.playing {
+            return;
+        }
+
+        let step = match self.backend {
+            ClockBackend::Audio => delta,
+            ClockBackend::Fixed(step) => step.raw(),
+        };
+
+        let predicted = self.position + step * self.rate.raw();
+
+        if reported.is_some_and(|reported| (reported.raw() - predicted.raw()).abs() <= DRIFT_SNAP) {
+            self.pending_seek = None;
+        }
+
+        self.position = match (self.backend, reported, self.pending_seek) {
+            (ClockBackend::Audio, Some(reported), None) => {
+                let drift = reported.raw() - predicted.raw();
+
+                if DRIFT_SNAP < drift.abs() {
+                    reported
+                } else {
+                    p32((predicted.raw() + drift * DRIFT_CORRECTION).max(self.position.raw()))
+                }
+            }
+            _ => predicted,
+        };
+    }
+}
+
+pub fn tick_song_clock(
+    time: Option<Res<Time>>,
+    mut audio_position: ResMut<AudioPosition>,
+    mut clock: ResMut<SongClock>,
+    mut song_time: ResMut<SongTime>,
+) {
+    let delta = time.map_or(0., |time| time.delta_seconds());
+    clock.advance(delta, audio_position.take());
+    **song_time = clock.position();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct SongClockLabel;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_correction() {
+        let mut clock = SongClock::default();
+
+        clock.advance(1., None);
+        assert_eq!(clock.position(), p32(0.));
+
+        clock.play();
+        clock.advance(0.5, None);
+        assert_eq!(clock.position(), p32(0.5));
+
+        clock.advance(0.5, Some(p32(1.05)));
+        assert!((clock.position().raw() - 1.005).abs() < 1e-5);
+
+        clock.advance(0.5, Some(p32(3.)));
+        assert_eq!(clock.position(), p32(3.));
+
+        clock.set_rate(p32(2.));
+        clock.advance(0.5, Some(p32(3.)));
+        assert_eq!(clock.position(), p32(3.));
+
+        clock.seek(p32(1.));
+        clock.advance(0.25, Some(p32(10.)));
+        assert_eq!(clock.position(), p32(1.5));
+        assert_eq!(clock.take_seek(), Some(p32(1.)));
+    }
+
+    #[test]
+    fn seek_handshake() {
+        let mut clock = SongClock::default();
+        clock.play();
+        clock.advance(0.5, Some(p32(0.5)));
+
+        clock.seek(p32(10.));
+        clock.advance(0.5, Some(p32(1.)));
+        assert_eq!(clock.position(), p32(10.5));
+        assert!(clock.is_seeking());
+
+        clock.advance(0.5, Some(p32(11.05)));
+        assert!((clock.position().raw() - 11.005).abs() < 1e-5);
+        assert!(!clock.is_seeking());
+
+        clock.advance(0.5, Some(p32(20.)));
+        assert_eq!(clock.position(), p32(20.));
+    }
+
+    #[test]
+    fn fixed_step() {
+        let mut clock = SongClock::fixed(0.25);
+        clock.play();
+
+        (0..4).for_each(|_| clock.advance(1., Some(p32(100.))));
+        assert_eq!(clock.position(), p32(1.));
+    }
+
+    #[test]
+    fn steps_sheet_pipeline() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.25))
+            .add_plugin(crate::sheet::SheetPlugin);
+
+        app.update();
+        assert_eq!(**app.world.resource::<SongTime>(), p32(0.));
+
+        app.world.resource_mut::<SongClock>().play();
+        (0..4).for_each(|_| app.update());
+        assert_eq!(**app.world.resource::<SongTime>(), p32(1.));
+    }
+}
@@ -1,41 +1,137 @@
-use crate::{sheet::*, utils::*, SongTime, MAX_CHANNELS};
+pub mod autoplay;
+pub mod hold;
+pub mod input;
+pub mod judgement;
+pub mod score;
+
+pub use autoplay::*;
+pub use hold::*;
+pub use input::*;
+pub use judgement::*;
+pub use score::*;
+
+use crate::{sheet::*, utils::*, SongTime, VisualTime, MAX_CHANNELS};
 
 use bevy::prelude::*;
-use derive_more::From;
+use derive_more::{BitOr, From};
 use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize};
 use tap::tap::Tap;
+use tinyvec::TinyVec;
+
+pub const MAX_LAYERS: u8 = 64;
+
+/// Set of hit layers, saved as the list of layers in it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, BitOr, Serialize, Deserialize)]
+#[serde(try_from = "Vec<u8>", into = "Vec<u8>")]
+pub struct LayerMask(pub u64);
+
+impl LayerMask {
+    pub fn layer(layer: u8) -> Self {
+        Self::checked(layer).unwrap_or_else(|| panic!("Layers must be below {MAX_LAYERS}"))
+    }
+
+    /// `None` if the layer isn't below [`MAX_LAYERS`]
+    pub fn checked(layer: u8) -> Option<Self> {
+        (layer < MAX_LAYERS).then(|| Self(1 << layer))
+    }
+
+    pub fn contains(self, layer: u8) -> bool {
+        layer < MAX_LAYERS && self.0 & (1 << layer) != 0
+    }
+
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn layers(self) -> impl Iterator<Item = u8> {
+        (0..MAX_LAYERS).filter(move |layer| self.contains(*layer))
+    }
+}
+
+impl TryFrom<Vec<u8>> for LayerMask {
+    type Error = String;
+
+    fn try_from(layers: Vec<u8>) -> Result<Self, Self::Error> {
+        layers
+            .into_iter()
+            .try_fold(Self(0), |mask, layer| {
+                Self::checked(layer).map(|layer| mask | layer)
+            })
+            .ok_or_else(|| format!("Layers must be below {MAX_LAYERS}"))
+    }
+}
+
+impl From<LayerMask> for Vec<u8> {
+    fn from(mask: LayerMask) -> Self {
+        mask.layers().collect()
+    }
+}
 
-enum PressKind {
+/// Names of the layers of the loaded chart, indexed by layer
+#[derive(Default, Deref, DerefMut)]
+pub struct LayerNames(pub Vec<String>);
+
+impl LayerNames {
+    /// `None` if any of the names aren't of a layer
+    pub fn mask(&self, names: &[&str]) -> Option<LayerMask> {
+        names.iter().try_fold(LayerMask(0), |mask, name| {
+            self.iter()
+                .position(|layer| layer == name)
+                .filter(|layer| *layer < MAX_LAYERS as usize)
+                .map(|layer| mask | LayerMask::layer(layer as u8))
+        })
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub enum PressKind {
     Press(N32),
     Hold(N32, N32),
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
-enum PressStrength {
+pub enum PressStrength {
+    #[default]
     Single = 1,
     Double = 2,
     Triple = 3,
 }
 
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct HitPrompt {
-    press_kind: PressKind,
-    press_strength: PressStrength,
-    press_phat_key: bool,
-    signal_layer: u8,
+    pub press_kind: PressKind,
+    pub press_strength: PressStrength,
+    pub press_phat_key: bool,
+    pub signal_layers: LayerMask,
 }
 
-#[derive(Clone, Copy)]
+impl HitPrompt {
+    /// When the prompt should be pressed
+    pub fn time(&self) -> N32 {
+        match self.press_kind {
+            PressKind::Press(time) | PressKind::Hold(time, _) => time,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 pub struct HitInfo {
     /// Object time is used instead of hit time to keep animations synced with music
     pub object_time: P32,
     pub hit_time: P32,
-    pub layer: u8,
+    pub layers: LayerMask,
+    pub strength: PressStrength,
+    pub phat: bool,
 }
 
-#[derive(Deref, DerefMut, From)]
-pub struct HitRegister(pub [Option<HitInfo>; 4]);
+/// Hits registered this frame. Cleared at the start of every frame, so hits have to be written
+/// between then and the [`SheetStage`](crate::sheet::SheetStage) and are only responded to once.
+#[derive(Default, Deref, DerefMut, From)]
+pub struct HitRegister(pub TinyVec<[HitInfo; 4]>);
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Serialize, Deserialize)]
 pub enum ResponseKind {
     Nil,
     /// Stays at 0 state until hit, once hit which it will commece from the current time
@@ -48,12 +144,46 @@ pub enum ResponseKind {
     /// Will stay at 0 state with no hit, for each hit it will play the automation
     /// from the hit time to hit time + excess.
     Follow(P32),
+    /// Stays at 0 state until held, plays from the current time while a layer is held and
+    /// stays where it was released otherwise
+    Hold,
+    /// Restarts the automation from the start of the sheet on every hit
+    Retrigger,
+    /// Steps through the next n automations (itself first) one hit at a time, wrapping around
+    Cycle(u8),
+    /// Holds the current time on a hit until the next hit resumes playing
+    Freeze,
+    /// Plays backwards from the hit time until it reaches the start of the sheet
+    Reverse,
 }
 
-#[derive(Component)]
+/// Which hits on its layers a response reacts to. Conditions on grades react to the judgements
+/// of presses and hold releases instead of the hits themselves.
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+pub enum ResponseCondition {
+    /// Every hit, whether or not it judged a prompt
+    #[default]
+    Any,
+    Miss,
+    MinGrade(Grade),
+}
+
+impl ResponseCondition {
+    fn accepts(&self, grade: Grade) -> bool {
+        match self {
+            ResponseCondition::Any => true,
+            ResponseCondition::Miss => grade == Grade::Miss,
+            ResponseCondition::MinGrade(min) => *min <= grade,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Serialize, Deserialize)]
 pub struct HitResponse {
     pub kind: ResponseKind,
-    pub layer: u8,
+    pub layers: LayerMask,
+    #[serde(default)]
+    pub condition: ResponseCondition,
 }
 
 #[derive(Component)]
@@ -61,6 +191,7 @@ pub enum ResponseState {
     Nil,
     Hit(P32),
     Delegated(bool),
+    Cycled(u8),
 }
 
 #[derive(Clone, Copy)]
@@ -70,8 +201,8 @@ pub struct ResponseOutput {
 }
 
 #[rustfmt::skip]
-fn clear_hit_responses(
-    time: Res<SongTime>,
+pub fn clear_hit_responses(
+    time: Res<VisualTime>,
     mut response_sheets: Query<(&SheetPosition, &mut ResponseState)>,
 ) {
     response_sheets
@@ -80,10 +211,18 @@ fn clear_hit_responses(
         .for_each(|(_, mut response_state)| *response_state = ResponseState::Nil);
 }
 
+pub fn clear_registers(mut hits: ResMut<HitRegister>, mut releases: ResMut<ReleaseRegister>) {
+    hits.clear();
+    releases.clear();
+}
+
+/// Hits are responded to in the order they were hit so toggles and cycles step in that order
 #[rustfmt::skip]
-fn respond_to_hits(
-    time: Res<SongTime>,
+pub fn respond_to_hits(
+    time: Res<VisualTime>,
     hits: Res<HitRegister>,
+    held: Res<HeldLayers>,
+    mut judgements: EventReader<Judgement>,
     hit_resps: Query<&HitResponse>,
     mut sheets: Query<(
         &SheetPosition,
@@ -93,35 +232,82 @@ fn respond_to_hits(
 )
     -> [ResponseOutput; MAX_CHANNELS]
 {
+    let mut hits = hits
+        .iter()
+        .map(|hit| (hit.object_time, hit.hit_time, hit.layers))
+        .collect::<Vec<_>>();
+
+    let mut judged = judgements
+        .iter()
+        .map(|j| (j.object_time, j.hit_time(), j.layers, j.grade))
+        .collect::<Vec<_>>();
+
+    hits.sort_by_key(|(_, hit_time, _)| *hit_time);
+    judged.sort_by_key(|(_, hit_time, ..)| *hit_time);
+
     [ResponseOutput { seek_time: **time, redirect: None }; MAX_CHANNELS].tap_mut(|outputs| {
         sheets
             .iter_mut()
             .filter(|(pos, ..)| f32::EPSILON < pos.duration.raw())
             .filter(|(pos, ..)| pos.scheduled_at(**time))
             .map(|(pos, instance, state)| (pos, hit_resps.get(**instance).unwrap() ,state))
-            .for_each(|(pos, HitResponse { kind, layer }, mut state)| {
+            .for_each(|(pos, HitResponse { kind, layers, condition }, mut state)| {
                 use ResponseKind::*;
                 use ResponseState::*;
 
-                hits.iter()
-                    .flatten()
-                    .filter(|hit| pos.scheduled_at(hit.hit_time) && hit.layer == *layer)
-                    .for_each(|hit| match (kind, &mut *state) {
+                let triggers = match condition {
+                    ResponseCondition::Any => hits.clone(),
+                    condition => judged
+                        .iter()
+                        .filter(|(.., grade)| condition.accepts(*grade))
+                        .map(|(object_time, hit_time, layers, _)| (*object_time, *hit_time, *layers))
+                        .collect(),
+                };
+
+                triggers
+                    .into_iter()
+                    .filter(|(_, hit_time, hit_layers)| pos.scheduled_at(*hit_time)
+                        && hit_layers.intersects(*layers)
+                    )
+                    .for_each(|(object_time, ..)| match (kind, &mut *state) {
                         (Commence | Switch(_), state) => *state = Delegated(true),
                         (Toggle(_), Delegated(delegate)) => *delegate = !*delegate,
                         (Toggle(_), state) => *state = Delegated(true),
-                        (Follow(_), last_hit) => *last_hit = Hit(hit.object_time),
+                        (Follow(_) | Retrigger | Reverse, last_hit) => {
+                            *last_hit = Hit(object_time)
+                        }
+                        (Cycle(n), Cycled(step)) => *step = (*step + 1) % (*n).max(1),
+                        (Cycle(n), state) => *state = Cycled(1 % (*n).max(1)),
+                        (Freeze, state) => *state = match state {
+                            Hit(_) => Delegated(false),
+                            _ => Hit(object_time),
+                        },
                         _ => {}
                     });
 
+                if let Hold = kind {
+                    match (held.is_holding(*layers), &*state) {
+                        (true, _) => *state = Delegated(true),
+                        (false, Delegated(true)) => *state = Hit(**time),
+                        _ => {}
+                    }
+                }
+
                 let adjusted_offset = match (kind, &mut *state) {
                     (Commence, Delegated(delegate)) if !*delegate => pos.start,
+                    (Hold, ResponseState::Nil) => pos.start,
+                    (Hold | Freeze, Hit(held)) => *held,
                     (Follow(ex), Hit(hit)) if !(*hit..*hit + ex).contains(&**time) => *hit + ex,
+                    (Retrigger, Hit(hit)) => pos.start + p32((time.raw() - hit.raw()).max(0.)),
+                    (Reverse, Hit(hit)) => {
+                        p32((2. * hit.raw() - time.raw()).max(pos.start.raw()).min(hit.raw()))
+                    }
                     _ => **time
                 };
 
                 let shift = match (kind, &mut *state) {
                     (Switch(shift) | Toggle(shift), Delegated(true)) => Some(*shift),
+                    (Cycle(_), Cycled(step)) => Some(*step),
                     _ => None
                 };
 
@@ -132,3 +318,193 @@ fn respond_to_hits(
             })
     })
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bevy::ecs::event::Events;
+
+    /// Hits and releases for tests to register on the next frame
+    #[derive(Default)]
+    pub(crate) struct NextRegisters(pub Vec<HitInfo>, pub Vec<HitRelease>);
+
+    pub(crate) fn register_next(
+        mut next: ResMut<NextRegisters>,
+        mut hits: ResMut<HitRegister>,
+        mut releases: ResMut<ReleaseRegister>,
+    ) {
+        hits.extend(next.0.drain(..));
+        releases.extend(next.1.drain(..));
+    }
+
+    /// Hit times and the object times and grades of judgements for each frame
+    type Frame<'a> = (f32, &'a [f32], &'a [(f32, Grade)]);
+
+    fn respond(response: HitResponse, frames: &[Frame]) -> Vec<(f32, Option<u8>)> {
+        let mut world = World::new();
+        world.insert_resource(VisualTime(p32(0.)));
+        world.init_resource::<HitRegister>();
+        world.init_resource::<HeldLayers>();
+        world.init_resource::<Events<Judgement>>();
+
+        let response = world.spawn().insert(response).id();
+        world.spawn().insert_bundle((
+            SheetPosition {
+                start: p32(1.),
+                duration: p32(10.),
+                coverage: Coverage(0, 0),
+            },
+            Instance::<HitResponse>::new(response),
+            ResponseState::Nil,
+        ));
+
+        let mut system = IntoSystem::into_system(respond_to_hits);
+        system.initialize(&mut world);
+
+        frames
+            .iter()
+            .map(|(time, hits, judged)| {
+                let register = hits
+                    .iter()
+                    .map(|hit| HitInfo {
+                        object_time: p32(*hit),
+                        hit_time: p32(*hit),
+                        layers: LayerMask::layer(0),
+                        strength: PressStrength::Single,
+                        phat: false,
+                    })
+                    .collect();
+
+                world.insert_resource(VisualTime(p32(*time)));
+                world.insert_resource(HitRegister(register));
+
+                judged.iter().for_each(|(object_time, grade)| {
+                    world.resource_mut::<Events<Judgement>>().send(Judgement {
+                        prompt: Entity::from_raw(0),
+                        grade: *grade,
+                        object_time: p32(*object_time),
+                        offset: None,
+                        layers: LayerMask::layer(0),
+                        strength: PressStrength::Single,
+                        kind: JudgementKind::Press,
+                    })
+                });
+
+                let output = system.run((), &mut world)[0];
+                (output.seek_time.raw(), output.redirect)
+            })
+            .collect()
+    }
+
+    fn responses(kind: ResponseKind, frames: &[(f32, &[f32])]) -> Vec<(f32, Option<u8>)> {
+        let frames = frames
+            .iter()
+            .map(|(time, hits)| (*time, *hits, &[][..]))
+            .collect::<Vec<_>>();
+
+        respond(
+            HitResponse {
+                kind,
+                layers: LayerMask::layer(0),
+                condition: ResponseCondition::Any,
+            },
+            &frames,
+        )
+    }
+
+    #[test]
+    fn response_kinds() {
+        use ResponseKind::*;
+
+        [
+            (
+                Commence,
+                &[(2., &[][..]), (3., &[3.]), (4., &[])][..],
+                [2., 3., 4.],
+            ),
+            (
+                Follow(p32(1.)),
+                &[(2., &[]), (3., &[3.]), (5., &[])],
+                [2., 3., 4.],
+            ),
+            (
+                Retrigger,
+                &[(2., &[]), (3., &[3.]), (4., &[])],
+                [2., 1., 2.],
+            ),
+            (
+                Freeze,
+                &[(2.5, &[2.]), (4., &[]), (5., &[5.])],
+                [2., 2., 5.],
+            ),
+            (Reverse, &[(3., &[3.]), (4., &[]), (6., &[])], [3., 2., 1.]),
+        ]
+        .into_iter()
+        .for_each(|(kind, frames, expected)| {
+            let seek_times = responses(kind, frames)
+                .into_iter()
+                .map(|(seek_time, _)| seek_time)
+                .collect::<Vec<_>>();
+
+            assert_eq!(seek_times, expected);
+        });
+
+        let redirects = responses(
+            Cycle(3),
+            &[(2., &[2.]), (3., &[3.]), (4., &[4.]), (5., &[])],
+        )
+        .into_iter()
+        .map(|(_, redirect)| redirect)
+        .collect::<Vec<_>>();
+
+        assert_eq!(redirects, [Some(1), Some(2), Some(0), Some(0)]);
+    }
+
+    #[test]
+    fn chronological_hits() {
+        let hits = [2.5, 2., 2.1, 2.2, 2.3];
+
+        assert_eq!(
+            responses(ResponseKind::Retrigger, &[(2.5, &hits)]),
+            [(1., None)]
+        );
+        assert_eq!(
+            responses(ResponseKind::Toggle(1), &[(2.5, &hits)]),
+            [(2.5, Some(1))]
+        );
+    }
+
+    #[test]
+    fn conditional_responses() {
+        let response = |kind, condition| HitResponse {
+            kind,
+            layers: LayerMask::layer(0),
+            condition,
+        };
+
+        assert_eq!(
+            respond(
+                response(ResponseKind::Toggle(1), ResponseCondition::Miss),
+                &[
+                    (2., &[2.], &[(2., Grade::Perfect)]),
+                    (3., &[], &[(2.8, Grade::Miss)]),
+                ]
+            ),
+            [(2., None), (3., Some(1))]
+        );
+
+        assert_eq!(
+            respond(
+                response(
+                    ResponseKind::Switch(1),
+                    ResponseCondition::MinGrade(Grade::Great)
+                ),
+                &[
+                    (2., &[2.], &[(2., Grade::Good)]),
+                    (3., &[3.], &[(3., Grade::Perfect)]),
+                ]
+            ),
+            [(2., None), (3., Some(1))]
+        );
+    }
+}
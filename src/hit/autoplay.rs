@@ -0,0 +1,255 @@
+use super::*;
+use crate::replay::Playback;
+
+/// Inserting this resource hits every prompt perfectly, in place of any other input except a
+/// replay being played back
+#[derive(Default)]
+pub struct Autoplay {
+    played_until: Option<N32>,
+}
+
+/// Hits prompts as their time is reached and releases holds at their end, with as many
+/// presses and releases as the prompt's strength. Prompts jumped past by a seek aren't hit.
+#[rustfmt::skip]
+pub fn autoplay(
+    time: Res<SongTime>,
+    autoplay: Option<ResMut<Autoplay>>,
+    playback: Option<Res<Playback>>,
+    prompts: Query<&HitPrompt>,
+    mut hits: ResMut<HitRegister>,
+    mut releases: ResMut<ReleaseRegister>,
+) {
+    let mut autoplay = match autoplay {
+        Some(autoplay) => autoplay,
+        None => return,
+    };
+
+    let now = n32(time.raw());
+
+    if playback.is_some() {
+        autoplay.played_until = Some(now);
+        return;
+    }
+    // Nothing is played on the first frame or after seeking backwards, so seeks don't hit
+    // every prompt up to where they land
+    let since = match autoplay.played_until {
+        Some(until) if until <= now => until,
+        _ => {
+            autoplay.played_until = Some(now);
+            hits.clear();
+            releases.clear();
+            return;
+        }
+    };
+    let due = |at: N32| since < at && at <= now;
+
+    let mut presses = prompts
+        .iter()
+        .filter(|prompt| due(prompt.time()))
+        .map(|prompt| HitInfo {
+            object_time: p32(prompt.time().raw().max(0.)),
+            hit_time: p32(prompt.time().raw().max(0.)),
+            layers: prompt.signal_layers,
+            strength: prompt.press_strength,
+            phat: prompt.press_phat_key,
+        })
+        .collect::<Vec<_>>();
+
+    let mut released = prompts
+        .iter()
+        .filter_map(|prompt| match prompt.press_kind {
+            PressKind::Hold(_, end) if due(end) => Some((end, prompt)),
+            _ => None,
+        })
+        .flat_map(|(end, prompt)| (0..prompt.press_strength as u8).map(move |_| HitRelease {
+            release_time: p32(end.raw().max(0.)),
+            layers: prompt.signal_layers,
+        }))
+        .collect::<Vec<_>>();
+
+    presses.sort_by_key(|hit| hit.hit_time);
+    released.sort_by_key(|release| release.release_time);
+
+    **hits = presses.into_iter().collect();
+    **releases = released.into_iter().collect();
+
+    autoplay.played_until = Some(now);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::Playback;
+    use crate::{clock::SongClock, replay::Replay};
+    use bevy::ecs::event::Events;
+
+    #[test]
+    fn replay_precedence() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.1))
+            .init_resource::<Autoplay>()
+            .insert_resource(Playback::new(Replay::default()))
+            .add_plugin(SheetPlugin);
+
+        app.world.spawn().insert(HitPrompt {
+            press_kind: PressKind::Press(n32(0.2)),
+            press_strength: PressStrength::Single,
+            press_phat_key: false,
+            signal_layers: LayerMask::layer(0),
+        });
+
+        app.world.resource_mut::<SongClock>().play();
+
+        let hits = (0..5)
+            .map(|_| {
+                app.update();
+                app.world.resource::<HitRegister>().len()
+            })
+            .sum::<usize>();
+
+        assert_eq!(hits, 0);
+
+        app.world.remove_resource::<Playback>();
+        (0..5).for_each(|_| app.update());
+        assert_eq!(app.world.resource::<Score>().count(Grade::Miss), 1);
+    }
+
+    #[test]
+    fn seeking() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.1))
+            .init_resource::<Autoplay>()
+            .add_plugin(SheetPlugin);
+
+        [0.5, 1.].into_iter().for_each(|time| {
+            app.world.spawn().insert(HitPrompt {
+                press_kind: PressKind::Press(n32(time)),
+                press_strength: PressStrength::Single,
+                press_phat_key: false,
+                signal_layers: LayerMask::layer(0),
+            });
+        });
+
+        let hit_times = |app: &mut App, frames| {
+            (0..frames)
+                .flat_map(|_| {
+                    app.update();
+                    app.world
+                        .resource::<HitRegister>()
+                        .iter()
+                        .map(|hit| hit.object_time.raw())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        app.world.resource_mut::<SongClock>().seek(p32(0.8));
+        assert!(hit_times(&mut app, 2).is_empty());
+
+        app.world.resource_mut::<SongClock>().play();
+        assert_eq!(hit_times(&mut app, 5), [1.]);
+
+        app.world.resource_mut::<SongClock>().seek(p32(0.7));
+        assert_eq!(hit_times(&mut app, 5), [1.]);
+    }
+
+    #[test]
+    fn perfect_play() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.1))
+            .init_resource::<Autoplay>()
+            .add_plugin(SheetPlugin);
+
+        [
+            (PressKind::Press(n32(0.5)), PressStrength::Single, false, 0),
+            (
+                PressKind::Hold(n32(1.), n32(2.)),
+                PressStrength::Double,
+                false,
+                1,
+            ),
+            (PressKind::Press(n32(1.5)), PressStrength::Triple, false, 2),
+            (PressKind::Press(n32(2.5)), PressStrength::Single, true, 3),
+            (PressKind::Press(n32(2.7)), PressStrength::Single, false, 2),
+        ]
+        .into_iter()
+        .for_each(
+            |(press_kind, press_strength, press_phat_key, signal_layer)| {
+                app.world.spawn().insert(HitPrompt {
+                    press_kind,
+                    press_strength,
+                    press_phat_key,
+                    signal_layers: LayerMask::layer(signal_layer),
+                });
+            },
+        );
+
+        let sheets = [
+            ResponseKind::Commence,
+            ResponseKind::Switch(1),
+            ResponseKind::Toggle(1),
+            ResponseKind::Follow(p32(0.2)),
+        ]
+        .into_iter()
+        .zip(0..)
+        .map(|(kind, layer)| {
+            let response = app
+                .world
+                .spawn()
+                .insert(HitResponse {
+                    kind,
+                    layers: LayerMask::layer(layer),
+                    condition: ResponseCondition::Any,
+                })
+                .id();
+
+            app.world
+                .spawn()
+                .insert(SheetPosition {
+                    start: p32(0.),
+                    duration: p32(4.),
+                    coverage: Coverage(layer, layer),
+                })
+                .insert(Instance::<HitResponse>::new(response))
+                .insert(ResponseState::Nil)
+                .id()
+        })
+        .collect::<Vec<_>>();
+
+        app.world.resource_mut::<SongClock>().play();
+
+        let mut holding = false;
+        let judgements = (0..35)
+            .flat_map(|_| {
+                app.update();
+                holding |= app
+                    .world
+                    .resource::<HeldLayers>()
+                    .is_holding(LayerMask::layer(1));
+                app.world
+                    .resource_mut::<Events<Judgement>>()
+                    .drain()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(judgements.len(), 6);
+        assert!(judgements
+            .iter()
+            .all(|judgement| judgement.grade == Grade::Perfect));
+        assert!(
+            holding
+                && !app
+                    .world
+                    .resource::<HeldLayers>()
+                    .is_holding(LayerMask::layer(1))
+        );
+        assert_eq!(app.world.resource::<Score>().max_combo, 6);
+
+        let state = |sheet: Entity| app.world.get::<ResponseState>(sheet).unwrap();
+        assert!(matches!(state(sheets[0]), ResponseState::Delegated(true)));
+        assert!(matches!(state(sheets[1]), ResponseState::Delegated(true)));
+        assert!(matches!(state(sheets[2]), ResponseState::Delegated(false)));
+        assert!(matches!(state(sheets[3]), ResponseState::Hit(time) if *time == p32(2.5)));
+    }
+}
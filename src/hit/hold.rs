@@ -0,0 +1,214 @@
+use super::*;
+use crate::latency::Latency;
+use std::collections::HashMap;
+
+use tinyvec::TinyVec;
+
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
+pub struct HitRelease {
+    pub release_time: P32,
+    pub layers: LayerMask,
+}
+
+/// Releases registered this frame. Cleared at the start of every frame like the [`HitRegister`].
+#[derive(Default, Deref, DerefMut, From)]
+pub struct ReleaseRegister(pub TinyVec<[HitRelease; 4]>);
+
+/// Buttons held down on each layer along with when that last changed
+#[derive(Default)]
+pub struct HeldLayers(HashMap<u8, (u32, P32)>);
+
+impl HeldLayers {
+    /// Whether any of the layers are held
+    pub fn is_holding(&self, layers: LayerMask) -> bool {
+        layers
+            .layers()
+            .any(|layer| self.0.get(&layer).is_some_and(|(held, _)| 0 < *held))
+    }
+
+    /// When a button on any of the layers was last pressed or released
+    pub fn changed_at(&self, layers: LayerMask) -> Option<P32> {
+        layers
+            .layers()
+            .filter_map(|layer| self.0.get(&layer).map(|(_, time)| *time))
+            .max()
+    }
+}
+
+/// Marks a hold prompt whose start was hit and whose release is still to be judged
+#[derive(Component)]
+pub struct ActiveHold;
+
+#[rustfmt::skip]
+pub fn track_holds(
+    hits: Res<HitRegister>,
+    releases: Res<ReleaseRegister>,
+    mut held: ResMut<HeldLayers>,
+) {
+    let presses = hits
+        .iter()
+        .map(|hit| (hit.hit_time, hit.layers, Some(hit.strength as u32)));
+
+    let releases = releases
+        .iter()
+        .map(|release| (release.release_time, release.layers, None));
+
+    let mut changes = presses.chain(releases).collect::<Vec<_>>();
+    changes.sort_by_key(|(time, ..)| *time);
+
+    changes.into_iter().for_each(|(time, layers, presses)| layers.layers().for_each(|layer| {
+        let (count, changed_at) = held.0.entry(layer).or_insert((0, time));
+
+        *count = match presses {
+            Some(presses) => *count + presses,
+            None => count.saturating_sub(1),
+        };
+        *changed_at = time;
+    }));
+}
+
+/// Holds kept until their end are perfect releases. Releases before the end are graded like hits
+/// if they are within the release leniency, otherwise they break the hold as a miss.
+#[rustfmt::skip]
+pub fn judge_holds(
+    mut commands: Commands,
+    time: Res<SongTime>,
+    latency: Res<Latency>,
+    windows: Res<TimingWindows>,
+    held: Res<HeldLayers>,
+    prompts: Query<(Entity, &HitPrompt), With<ActiveHold>>,
+    mut judgements: EventWriter<Judgement>,
+) {
+    let now = latency.hit_time(**time);
+
+    prompts
+        .iter()
+        .filter_map(|(entity, prompt)| match prompt.press_kind {
+            PressKind::Hold(_, end) => Some((entity, prompt, end)),
+            PressKind::Press(_) => None,
+        })
+        .filter_map(|(entity, prompt, end)| {
+            let layers = prompt.signal_layers;
+            let released = (!held.is_holding(layers))
+                .then(|| held.changed_at(layers).unwrap_or(now));
+
+            let offset = match released {
+                Some(release) => r32(release.raw() - end.raw()),
+                None if end.raw() <= now.raw() => r32(0.),
+                None => return None,
+            };
+
+            let grade = match offset.raw() {
+                offset if 0. <= offset => Grade::Perfect,
+                offset if windows.release_leniency.raw() < -offset => Grade::Miss,
+                _ => windows.grade(offset).unwrap_or(Grade::Miss),
+            };
+
+            Some(Judgement {
+                prompt: entity,
+                grade,
+                object_time: p32(end.raw().max(0.)),
+                offset: Some(offset),
+                layers,
+                strength: prompt.press_strength,
+                kind: JudgementKind::Release,
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .for_each(|judgement| {
+            commands.entity(judgement.prompt).remove::<ActiveHold>();
+            judgements.send(judgement);
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SongClock,
+        hit::tests::{register_next, NextRegisters},
+        sheet::SheetPlugin,
+    };
+    use bevy::ecs::event::Events;
+
+    #[test]
+    fn hold_releases() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.25))
+            .init_resource::<NextRegisters>()
+            .add_plugin(SheetPlugin)
+            .add_system_to_stage(CoreStage::PreUpdate, register_next);
+
+        let prompts = [(0.5, 1.5, 0), (0.5, 1.5, 1), (0.5, 1.5, 2)].map(|(start, end, layer)| {
+            app.world
+                .spawn()
+                .insert(HitPrompt {
+                    press_kind: PressKind::Hold(n32(start), n32(end)),
+                    press_strength: PressStrength::Single,
+                    press_phat_key: false,
+                    signal_layers: LayerMask::layer(layer),
+                })
+                .id()
+        });
+
+        let press = |layer| HitInfo {
+            object_time: p32(0.5),
+            hit_time: p32(0.5),
+            layers: LayerMask::layer(layer),
+            strength: PressStrength::Single,
+            phat: false,
+        };
+        let release = |release_time: f32, layer| HitRelease {
+            release_time: p32(release_time),
+            layers: LayerMask::layer(layer),
+        };
+
+        // Layer 0 holds through, layer 1 lets go within leniency, layer 2 lets go too early
+        let frames = [
+            (vec![], vec![]),
+            (vec![], vec![]),
+            (vec![press(0), press(1), press(2)], vec![]),
+            (vec![], vec![]),
+            (vec![], vec![release(0.9, 2)]),
+            (vec![], vec![release(1.45, 1)]),
+            (vec![], vec![]),
+        ];
+
+        app.world.resource_mut::<SongClock>().play();
+
+        let mut holding = vec![];
+        let mut judgements = frames
+            .into_iter()
+            .flat_map(|(hits, releases)| {
+                *app.world.resource_mut::<NextRegisters>() = NextRegisters(hits, releases);
+                app.update();
+                holding.push(
+                    app.world
+                        .resource::<HeldLayers>()
+                        .is_holding(LayerMask::layer(0)),
+                );
+                app.world
+                    .resource_mut::<Events<Judgement>>()
+                    .drain()
+                    .filter(|judgement| judgement.kind == JudgementKind::Release)
+                    .map(|judgement| (judgement.prompt, judgement.grade))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        judgements.sort();
+        assert_eq!(
+            judgements,
+            vec![
+                (prompts[0], Grade::Perfect),
+                (prompts[1], Grade::Great),
+                (prompts[2], Grade::Miss),
+            ]
+        );
+        assert_eq!(&holding[..4], [false, false, true, true]);
+        assert!(prompts
+            .iter()
+            .all(|prompt| app.world.get::<ActiveHold>(*prompt).is_none()));
+    }
+}
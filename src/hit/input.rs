@@ -0,0 +1,473 @@
+use super::*;
+use crate::{clock::SongClockLabel, latency::Latency};
+use std::collections::{HashMap, HashSet};
+
+use bevy::input::{keyboard::KeyboardInput, mouse::MouseButtonInput, ElementState, InputSystem};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HitButton {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// Phat keys only chord with other phat keys and only satisfy phat prompts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HitBinding {
+    pub layers: LayerMask,
+    pub phat: bool,
+}
+
+/// Hit layers and key class each button signals
+#[derive(Deref, DerefMut)]
+pub struct HitBindings(pub HashMap<HitButton, HitBinding>);
+
+impl Default for HitBindings {
+    fn default() -> Self {
+        let binding = |layer, phat| HitBinding {
+            layers: LayerMask::layer(layer),
+            phat,
+        };
+
+        Self(HashMap::from([
+            (HitButton::Key(KeyCode::Z), binding(0, false)),
+            (HitButton::Key(KeyCode::X), binding(1, false)),
+            (HitButton::Key(KeyCode::Space), binding(0, true)),
+            (HitButton::Mouse(MouseButton::Left), binding(0, false)),
+            (HitButton::Mouse(MouseButton::Right), binding(1, false)),
+        ]))
+    }
+}
+
+/// Presses with the same binding this many seconds after the first press of a chord join it
+#[derive(Deref, DerefMut)]
+pub struct ChordTolerance(pub P32);
+
+impl Default for ChordTolerance {
+    fn default() -> Self {
+        Self(p32(0.03))
+    }
+}
+
+/// Presses grouped into a chord that may still be joined
+pub struct Chord {
+    pub time: P32,
+    pub binding: HitBinding,
+    pub presses: u8,
+}
+
+impl Chord {
+    pub fn strength(&self) -> PressStrength {
+        match self.presses {
+            0 | 1 => PressStrength::Single,
+            2 => PressStrength::Double,
+            _ => PressStrength::Triple,
+        }
+    }
+}
+
+/// A press or release at a point in song time. Sources with precise timestamps (platform hooks,
+/// replays, tests) should send these directly instead of going through [`stamp_inputs`].
+#[derive(Debug, Clone, Copy)]
+pub struct TimedInput {
+    pub button: HitButton,
+    pub time: P32,
+    pub pressed: bool,
+}
+
+/// Bevy's input events carry no timestamp, only that they happened since the last frame. They
+/// are stamped halfway between the song time of the last frame and this one, which is where
+/// they land on average, instead of being stamped late by the whole frame.
+#[rustfmt::skip]
+pub fn stamp_inputs(
+    time: Res<SongTime>,
+    mut last_frame: Local<Option<P32>>,
+    mut held: Local<HashSet<HitButton>>,
+    mut keys: EventReader<KeyboardInput>,
+    mut mouse_buttons: EventReader<MouseButtonInput>,
+    mut inputs: EventWriter<TimedInput>,
+) {
+    let keys = keys
+        .iter()
+        .filter_map(|input| input.key_code.map(|key| (HitButton::Key(key), input.state)));
+
+    let mouse_buttons = mouse_buttons
+        .iter()
+        .map(|input| (HitButton::Mouse(input.button), input.state));
+
+    let since = last_frame.replace(**time).unwrap_or(**time).min(**time);
+    let stamp = (since + **time) / 2.;
+
+    keys.chain(mouse_buttons)
+        .filter_map(|(button, state)| match state {
+            // Key repeats arrive as more presses
+            ElementState::Pressed => held.insert(button).then_some((button, true)),
+            ElementState::Released => held.remove(&button).then_some((button, false)),
+        })
+        .for_each(|(button, pressed)| inputs.send(TimedInput { button, time: stamp, pressed }))
+}
+
+/// Chords are only registered as a hit once the chord tolerance has passed or they can't
+/// get any stronger. Releases of a binding are held back while it has a chord pending so they
+/// are never registered before the press they release. Input times are corrected for latency
+/// and object time is resolved to the closest unjudged prompt on the layers of the hit that
+/// isn't past the miss window.
+#[rustfmt::skip]
+pub fn capture_hits(
+    time: Res<SongTime>,
+    latency: Res<Latency>,
+    bindings: Res<HitBindings>,
+    tolerance: Res<ChordTolerance>,
+    windows: Res<TimingWindows>,
+    prompts: Query<&HitPrompt, Without<Consumed>>,
+    mut chords: Local<Vec<Chord>>,
+    mut held_releases: Local<Vec<(P32, HitBinding)>>,
+    mut inputs: EventReader<TimedInput>,
+    mut hits: ResMut<HitRegister>,
+    mut releases: ResMut<ReleaseRegister>,
+) {
+    let (mut presses, released): (Vec<_>, Vec<_>) = inputs
+        .iter()
+        .filter_map(|input| bindings
+            .get(&input.button)
+            .map(|binding| (latency.hit_time(input.time), *binding, input.pressed))
+        )
+        .partition(|(.., pressed)| *pressed);
+
+    presses.sort_by_key(|(time, ..)| *time);
+    held_releases.extend(released.into_iter().map(|(time, binding, _)| (time, binding)));
+
+    presses.into_iter().for_each(|(time, binding, _)| {
+        let joined = chords.iter_mut().find(|chord| chord.binding == binding
+            && chord.strength() != PressStrength::Triple
+            && time.raw() - chord.time.raw() <= tolerance.raw()
+        );
+
+        match joined {
+            Some(chord) => chord.presses += 1,
+            None => chords.push(Chord { time, binding, presses: 1 }),
+        }
+    });
+
+    let now = latency.hit_time(**time);
+    let (ready, pending) = chords
+        .drain(..)
+        .partition::<Vec<_>, _>(|chord| chord.strength() == PressStrength::Triple
+            || chord.time.raw() + tolerance.raw() <= now.raw()
+        );
+
+    *chords = pending;
+
+    let (mut released, still_held) = held_releases
+        .drain(..)
+        .partition::<Vec<_>, _>(|(_, binding)| chords
+            .iter()
+            .all(|chord| chord.binding != *binding)
+        );
+
+    *held_releases = still_held;
+    released.sort_by_key(|(time, _)| *time);
+
+    releases.clear();
+    releases.extend(released
+        .into_iter()
+        .map(|(release_time, binding)| HitRelease { release_time, layers: binding.layers })
+    );
+
+    hits.clear();
+    hits.extend(ready.into_iter().map(|chord| {
+        let object_time = prompts
+            .iter()
+            .filter(|prompt| prompt.signal_layers.intersects(chord.binding.layers))
+            .map(|prompt| p32(prompt.time().raw().max(0.)))
+            .filter(|time| chord.time.raw() - windows.miss.raw() <= time.raw())
+            .min_by_key(|time| p32((time.raw() - chord.time.raw()).abs()))
+            .unwrap_or(chord.time);
+
+        HitInfo {
+            object_time,
+            hit_time: chord.time,
+            layers: chord.binding.layers,
+            strength: chord.strength(),
+            phat: chord.binding.phat,
+        }
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct HitInputLabel;
+
+/// Needs Bevy's `InputPlugin` for keyboard and mouse input
+pub struct HitInputPlugin;
+
+impl Plugin for HitInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HitBindings>()
+            .init_resource::<ChordTolerance>()
+            .add_event::<TimedInput>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                stamp_inputs
+                    .label(HitInputLabel)
+                    .after(SongClockLabel)
+                    .after(InputSystem),
+            )
+            .add_system_to_stage(CoreStage::PreUpdate, capture_hits.after(HitInputLabel));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::SongClock, sheet::SheetPlugin};
+    use bevy::{ecs::event::Events, input::InputPlugin};
+
+    fn send<T: Send + Sync + 'static>(app: &mut App, event: T) {
+        app.world.resource_mut::<Events<T>>().send(event);
+    }
+
+    #[test]
+    fn synthetic_input() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.5))
+            .add_plugin(InputPlugin)
+            .add_plugin(SheetPlugin)
+            .add_plugin(HitInputPlugin);
+
+        [(1., 0), (2.5, 0), (1.5, 1)]
+            .into_iter()
+            .for_each(|(time, layer)| {
+                app.world.spawn().insert(HitPrompt {
+                    press_kind: PressKind::Press(n32(time)),
+                    press_strength: PressStrength::Single,
+                    press_phat_key: false,
+                    signal_layers: LayerMask::layer(layer),
+                });
+            });
+
+        app.world.resource_mut::<SongClock>().play();
+        app.update();
+        app.update();
+
+        let press = |key_code, state| KeyboardInput {
+            scan_code: 0,
+            key_code: Some(key_code),
+            state,
+        };
+
+        let hits = |app: &App| {
+            app.world
+                .resource::<HitRegister>()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        send(&mut app, press(KeyCode::Z, ElementState::Pressed));
+        send(&mut app, press(KeyCode::Z, ElementState::Pressed));
+        send(&mut app, press(KeyCode::X, ElementState::Pressed));
+        send(
+            &mut app,
+            MouseButtonInput {
+                button: MouseButton::Middle,
+                state: ElementState::Pressed,
+            },
+        );
+        // Stamped half a frame back, so the chord tolerance has already passed
+        app.update();
+        let registered = hits(&app);
+        assert_eq!(registered.len(), 2);
+        assert!(registered.iter().all(|hit| hit.hit_time == p32(1.25)));
+        assert_eq!(
+            (registered[0].layers, registered[0].object_time),
+            (LayerMask::layer(0), p32(2.5))
+        );
+        assert_eq!(
+            (registered[1].layers, registered[1].object_time),
+            (LayerMask::layer(1), p32(1.5))
+        );
+        assert!(registered
+            .iter()
+            .all(|hit| hit.strength == PressStrength::Single && !hit.phat));
+
+        send(&mut app, press(KeyCode::Z, ElementState::Released));
+        send(&mut app, press(KeyCode::Z, ElementState::Pressed));
+        send(
+            &mut app,
+            MouseButtonInput {
+                button: MouseButton::Left,
+                state: ElementState::Pressed,
+            },
+        );
+        send(&mut app, press(KeyCode::Space, ElementState::Pressed));
+        app.update();
+
+        let registered = hits(&app);
+        assert_eq!(registered.len(), 2);
+        assert!(registered.iter().all(|hit| hit.hit_time == p32(1.75)));
+        assert_eq!(
+            registered
+                .iter()
+                .map(|hit| (hit.strength, hit.phat))
+                .collect::<Vec<_>>(),
+            [
+                (PressStrength::Double, false),
+                (PressStrength::Single, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn late_object_time() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.01))
+            .add_plugin(InputPlugin)
+            .add_plugin(SheetPlugin)
+            .add_plugin(HitInputPlugin);
+
+        [1., 2.5, 1.01].into_iter().for_each(|time| {
+            app.world.spawn().insert(HitPrompt {
+                press_kind: PressKind::Press(n32(time)),
+                press_strength: PressStrength::Single,
+                press_phat_key: false,
+                signal_layers: LayerMask::layer(0),
+            });
+        });
+
+        let consumed = app
+            .world
+            .query::<(Entity, &HitPrompt)>()
+            .iter(&app.world)
+            .find(|(_, prompt)| prompt.time() == n32(1.01))
+            .map(|(entity, _)| entity)
+            .unwrap();
+        app.world.entity_mut(consumed).insert(Consumed);
+
+        app.world.resource_mut::<SongClock>().seek(p32(1.));
+        app.world.resource_mut::<SongClock>().play();
+        app.update();
+
+        send(
+            &mut app,
+            TimedInput {
+                button: HitButton::Key(KeyCode::Z),
+                time: p32(1.02),
+                pressed: true,
+            },
+        );
+
+        let object_times = (0..10)
+            .flat_map(|_| {
+                app.update();
+                app.world
+                    .resource::<HitRegister>()
+                    .iter()
+                    .map(|hit| hit.object_time)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(object_times, [p32(1.)]);
+    }
+
+    #[test]
+    fn short_taps() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.01))
+            .add_plugin(InputPlugin)
+            .add_plugin(SheetPlugin)
+            .add_plugin(HitInputPlugin);
+
+        app.world.resource_mut::<SongClock>().play();
+
+        let registered = [Some(true), Some(false), None, None, None, None]
+            .into_iter()
+            .map(|pressed| {
+                if let Some(pressed) = pressed {
+                    let time = **app.world.resource::<SongTime>();
+                    send(
+                        &mut app,
+                        TimedInput {
+                            button: HitButton::Key(KeyCode::Z),
+                            time,
+                            pressed,
+                        },
+                    );
+                }
+
+                app.update();
+                (
+                    app.world.resource::<HitRegister>().len(),
+                    app.world.resource::<ReleaseRegister>().len(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(registered.iter().filter(|(hits, _)| *hits == 1).count(), 1);
+        assert!(registered.contains(&(1, 1)));
+        assert!(!app
+            .world
+            .resource::<HeldLayers>()
+            .is_holding(LayerMask::layer(0)));
+    }
+
+    #[test]
+    fn chord_tolerance() {
+        [0., 0.05].into_iter().for_each(|input| {
+            let mut latency = Latency::default();
+            latency.global.input = r32(input);
+            chord_tolerance_with(latency);
+        });
+    }
+
+    fn chord_tolerance_with(latency: Latency) {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.01))
+            .insert_resource(latency)
+            .add_plugin(InputPlugin)
+            .add_plugin(SheetPlugin)
+            .add_plugin(HitInputPlugin);
+
+        app.world.resource_mut::<SongClock>().seek(p32(1.));
+        app.world.resource_mut::<SongClock>().play();
+        app.update();
+
+        let strengths = [
+            &[HitButton::Key(KeyCode::Z)][..],
+            &[HitButton::Mouse(MouseButton::Left)],
+            &[],
+            &[],
+            &[],
+            &[
+                HitButton::Key(KeyCode::Z),
+                HitButton::Mouse(MouseButton::Left),
+            ],
+            &[],
+            &[],
+            &[],
+            &[],
+        ]
+        .into_iter()
+        .flat_map(|buttons| {
+            buttons.iter().for_each(|button| {
+                let time = **app.world.resource::<SongTime>();
+                send(
+                    &mut app,
+                    TimedInput {
+                        button: *button,
+                        time,
+                        pressed: true,
+                    },
+                );
+            });
+            app.update();
+            app.world
+                .resource::<HitRegister>()
+                .iter()
+                .map(|hit| hit.strength)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(strengths, [PressStrength::Double, PressStrength::Double]);
+    }
+}
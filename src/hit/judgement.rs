@@ -0,0 +1,311 @@
+use super::*;
+use crate::latency::Latency;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Grade {
+    Miss,
+    Good,
+    Great,
+    Perfect,
+}
+
+/// Half widths in seconds either side of a prompt. Presses outside `good` but inside `miss`
+/// still consume the prompt, as a miss.
+#[derive(Debug, Clone, Copy)]
+pub struct TimingWindows {
+    pub perfect: P32,
+    pub great: P32,
+    pub good: P32,
+    pub miss: P32,
+    /// How early a hold can be released without breaking it
+    pub release_leniency: P32,
+}
+
+impl Default for TimingWindows {
+    fn default() -> Self {
+        Self {
+            perfect: p32(0.03),
+            great: p32(0.06),
+            good: p32(0.1),
+            miss: p32(0.15),
+            release_leniency: p32(0.1),
+        }
+    }
+}
+
+impl TimingWindows {
+    pub fn grade(&self, offset: R32) -> Option<Grade> {
+        match offset.abs().raw() {
+            offset if offset <= self.perfect.raw() => Some(Grade::Perfect),
+            offset if offset <= self.great.raw() => Some(Grade::Great),
+            offset if offset <= self.good.raw() => Some(Grade::Good),
+            offset if offset <= self.miss.raw() => Some(Grade::Miss),
+            _ => None,
+        }
+    }
+}
+
+/// Marks a prompt that has already been judged
+#[derive(Component)]
+pub struct Consumed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum JudgementKind {
+    Press,
+    /// End of a hold
+    Release,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Judgement {
+    pub prompt: Entity,
+    pub grade: Grade,
+    /// When the prompt should have been pressed or released
+    pub object_time: P32,
+    /// Hit time - object time. `None` if the prompt passed without a hit
+    pub offset: Option<R32>,
+    pub layers: LayerMask,
+    pub strength: PressStrength,
+    pub kind: JudgementKind,
+}
+
+impl Judgement {
+    /// Object time for prompts that passed without a hit
+    pub fn hit_time(&self) -> P32 {
+        self.offset.map_or(self.object_time, |offset| {
+            p32((self.object_time.raw() + offset.raw()).max(0.))
+        })
+    }
+}
+
+/// Each hit consumes the earliest unjudged prompt on its layers within the miss window whose
+/// strength and key class it matches.
+/// Prompts that are no longer hittable are judged as misses. Holds whose start is missed
+/// also miss their release, otherwise their release is left to [`judge_holds`].
+#[rustfmt::skip]
+pub fn judge_hits(
+    mut commands: Commands,
+    time: Res<SongTime>,
+    latency: Res<Latency>,
+    windows: Res<TimingWindows>,
+    hits: Res<HitRegister>,
+    prompts: Query<(Entity, &HitPrompt), Without<Consumed>>,
+    mut judgements: EventWriter<Judgement>,
+) {
+    let now = latency.hit_time(**time);
+    let mut consumed = HashSet::new();
+    let mut judged = vec![];
+
+    let mut prompts = prompts.iter().collect::<Vec<_>>();
+    prompts.sort_by_key(|(_, prompt)| prompt.time());
+
+    let mut hits = hits.iter().collect::<Vec<_>>();
+    hits.sort_by_key(|hit| hit.hit_time);
+
+    hits.into_iter().for_each(|hit| {
+        let judgement = prompts
+            .iter()
+            .filter(|(entity, prompt)| prompt.signal_layers.intersects(hit.layers)
+                && prompt.press_strength == hit.strength
+                && prompt.press_phat_key == hit.phat
+                && !consumed.contains(entity)
+            )
+            .map(|(entity, prompt)| (
+                *entity,
+                prompt.signal_layers,
+                r32(hit.hit_time.raw() - prompt.time().raw())
+            ))
+            .find_map(|(entity, layers, offset)| windows
+                .grade(offset)
+                .map(|grade| (entity, layers, offset, grade))
+            );
+
+        if let Some((prompt, layers, offset, grade)) = judgement {
+            consumed.insert(prompt);
+            judged.push(Judgement {
+                prompt,
+                grade,
+                object_time: p32((hit.hit_time.raw() - offset.raw()).max(0.)),
+                offset: Some(offset),
+                layers,
+                strength: hit.strength,
+                kind: JudgementKind::Press,
+            });
+        }
+    });
+
+    prompts
+        .iter()
+        .filter(|(entity, prompt)| !consumed.contains(entity)
+            && prompt.time().raw() + windows.miss.raw() < now.raw()
+        )
+        .for_each(|(entity, prompt)| judged.push(Judgement {
+            prompt: *entity,
+            grade: Grade::Miss,
+            object_time: p32(prompt.time().raw().max(0.)),
+            offset: None,
+            layers: prompt.signal_layers,
+            strength: prompt.press_strength,
+            kind: JudgementKind::Press,
+        }));
+
+    let hold_ends = prompts
+        .iter()
+        .filter_map(|(entity, prompt)| match prompt.press_kind {
+            PressKind::Hold(_, end) => Some((*entity, p32(end.raw().max(0.)))),
+            PressKind::Press(_) => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    judged.into_iter().for_each(|judgement| {
+        commands.entity(judgement.prompt).insert(Consumed);
+        judgements.send(judgement);
+
+        match (hold_ends.get(&judgement.prompt), judgement.grade) {
+            (None, _) => {}
+            (Some(end), Grade::Miss) => judgements.send(Judgement {
+                object_time: *end,
+                offset: None,
+                kind: JudgementKind::Release,
+                ..judgement
+            }),
+            (Some(_), _) => {
+                commands.entity(judgement.prompt).insert(ActiveHold);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SongClock,
+        hit::tests::{register_next, NextRegisters},
+        latency::LatencyOffsets,
+        sheet::SheetPlugin,
+    };
+    use bevy::ecs::event::Events;
+
+    #[test]
+    fn grades() {
+        let windows = TimingWindows::default();
+
+        [
+            (0., Some(Grade::Perfect)),
+            (-0.05, Some(Grade::Great)),
+            (0.08, Some(Grade::Good)),
+        ]
+        .into_iter()
+        .chain([(-0.12, Some(Grade::Miss)), (0.2, None)])
+        .for_each(|(offset, grade)| assert_eq!(windows.grade(r32(offset)), grade));
+    }
+
+    #[test]
+    fn late_misses() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.05))
+            .insert_resource(Latency {
+                global: LatencyOffsets {
+                    input: r32(0.1),
+                    ..default()
+                },
+                ..default()
+            })
+            .add_plugin(SheetPlugin);
+
+        app.world.spawn().insert(HitPrompt {
+            press_kind: PressKind::Press(n32(1.)),
+            press_strength: PressStrength::Single,
+            press_phat_key: false,
+            signal_layers: LayerMask::layer(0),
+        });
+
+        app.world.resource_mut::<SongClock>().play();
+
+        let missed_at = (0..40)
+            .find_map(|_| {
+                app.update();
+                let time = **app.world.resource::<SongTime>();
+                let judged = app
+                    .world
+                    .resource_mut::<Events<Judgement>>()
+                    .drain()
+                    .count();
+                (0 < judged).then_some(time)
+            })
+            .unwrap();
+
+        assert!((missed_at.raw() - 1.3).abs() < 1e-4);
+    }
+
+    #[test]
+    fn consumed_once() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.5))
+            .init_resource::<NextRegisters>()
+            .add_plugin(SheetPlugin)
+            .add_system_to_stage(CoreStage::PreUpdate, register_next);
+
+        let prompts = [(1., 0), (1.05, 0), (1.5, 1)].map(|(time, layer)| {
+            app.world
+                .spawn()
+                .insert(HitPrompt {
+                    press_kind: PressKind::Press(n32(time)),
+                    press_strength: PressStrength::Single,
+                    press_phat_key: false,
+                    signal_layers: LayerMask::layer(layer),
+                })
+                .id()
+        });
+
+        let hit = |hit_time: f32| HitInfo {
+            object_time: p32(hit_time),
+            hit_time: p32(hit_time),
+            layers: LayerMask::layer(0),
+            strength: PressStrength::Single,
+            phat: false,
+        };
+
+        let registers = [
+            vec![],
+            vec![],
+            vec![hit(1.01), hit(0.99), hit(1.4)],
+            vec![],
+            vec![],
+        ];
+
+        app.world.resource_mut::<SongClock>().play();
+
+        let judgements = registers
+            .into_iter()
+            .flat_map(|register| {
+                *app.world.resource_mut::<NextRegisters>() = NextRegisters(register, vec![]);
+                app.update();
+                app.world
+                    .resource_mut::<Events<Judgement>>()
+                    .drain()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(judgements.len(), 3);
+        assert_eq!(
+            (judgements[0].prompt, judgements[0].grade),
+            (prompts[0], Grade::Perfect)
+        );
+        assert_eq!(
+            (judgements[1].prompt, judgements[1].grade),
+            (prompts[1], Grade::Great)
+        );
+        assert_eq!(
+            (judgements[2].prompt, judgements[2].grade),
+            (prompts[2], Grade::Miss)
+        );
+        assert_eq!(judgements[2].offset, None);
+        assert!(prompts
+            .iter()
+            .all(|prompt| app.world.get::<Consumed>(*prompt).is_some()));
+    }
+}
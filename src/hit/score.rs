@@ -0,0 +1,201 @@
+use super::*;
+
+/// Base points of a perfect single press
+pub const BASE_POINTS: f32 = 300.;
+
+impl Grade {
+    /// Portion of a judgement's weight earned towards accuracy
+    pub fn accuracy(self) -> f32 {
+        match self {
+            Grade::Perfect => 1.,
+            Grade::Great => 0.7,
+            Grade::Good => 0.4,
+            Grade::Miss => 0.,
+        }
+    }
+}
+
+impl Judgement {
+    /// Chords weigh as much as their presses. A hold weighs once for its press and once more
+    /// for being kept until its release.
+    pub fn weight(&self) -> f32 {
+        self.strength as u8 as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    D,
+    C,
+    B,
+    A,
+    S,
+    SS,
+}
+
+/// Minimum accuracy percentage for each rank. Anything below `c` is a D.
+#[derive(Debug, Clone, Copy)]
+pub struct RankThresholds {
+    pub ss: P32,
+    pub s: P32,
+    pub a: P32,
+    pub b: P32,
+    pub c: P32,
+}
+
+impl Default for RankThresholds {
+    fn default() -> Self {
+        Self {
+            ss: p32(100.),
+            s: p32(95.),
+            a: p32(90.),
+            b: p32(80.),
+            c: p32(70.),
+        }
+    }
+}
+
+impl RankThresholds {
+    pub fn rank(&self, accuracy: P32) -> Rank {
+        [
+            (self.ss, Rank::SS),
+            (self.s, Rank::S),
+            (self.a, Rank::A),
+            (self.b, Rank::B),
+            (self.c, Rank::C),
+        ]
+        .into_iter()
+        .find_map(|(threshold, rank)| (threshold <= accuracy).then_some(rank))
+        .unwrap_or(Rank::D)
+    }
+}
+
+/// Running totals over every judgement so far. Judgements of a frame are scored in the order
+/// they happened in, whatever order they were sent in, so the same play always scores the same.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Score {
+    pub points: u64,
+    pub combo: u32,
+    pub max_combo: u32,
+    /// Judgements per grade, indexed by [`Grade`]
+    pub grades: [u32; 4],
+    earned: f32,
+    possible: f32,
+}
+
+impl Score {
+    pub fn reduce<'a>(judgements: impl IntoIterator<Item = &'a Judgement>) -> Self {
+        Self::default().tap_mut(|score| judgements.into_iter().for_each(|j| score.judge(j)))
+    }
+
+    pub fn judge(&mut self, judgement: &Judgement) {
+        let weight = judgement.weight();
+        let earned = weight * judgement.grade.accuracy();
+
+        self.combo = match judgement.grade {
+            Grade::Miss => 0,
+            _ => self.combo + 1,
+        };
+        self.max_combo = self.max_combo.max(self.combo);
+        self.grades[judgement.grade as usize] += 1;
+        self.points += (BASE_POINTS * earned).round() as u64;
+        self.earned += earned;
+        self.possible += weight;
+    }
+
+    /// Judges in order of when they happened
+    pub fn judge_frame<'a>(&mut self, judgements: impl IntoIterator<Item = &'a Judgement>) {
+        let mut judgements = judgements.into_iter().collect::<Vec<_>>();
+        judgements.sort_by_key(|j| (j.hit_time(), j.object_time, j.kind, j.layers.0, j.prompt));
+        judgements
+            .into_iter()
+            .for_each(|judgement| self.judge(judgement));
+    }
+
+    pub fn count(&self, grade: Grade) -> u32 {
+        self.grades[grade as usize]
+    }
+
+    /// Percentage of the weighted judgements earned, 100 before anything is judged
+    pub fn accuracy(&self) -> P32 {
+        match self.possible {
+            possible if possible <= 0. => p32(100.),
+            possible => p32(100. * self.earned / possible),
+        }
+    }
+
+    pub fn rank(&self, thresholds: &RankThresholds) -> Rank {
+        thresholds.rank(self.accuracy())
+    }
+}
+
+pub fn score_judgements(mut judgements: EventReader<Judgement>, mut score: ResMut<Score>) {
+    score.judge_frame(judgements.iter());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn judgement(grade: Grade, strength: PressStrength, kind: JudgementKind) -> Judgement {
+        Judgement {
+            prompt: Entity::from_raw(0),
+            grade,
+            object_time: p32(0.),
+            offset: None,
+            layers: LayerMask::layer(0),
+            strength,
+            kind,
+        }
+    }
+
+    #[test]
+    fn weighted_score() {
+        use JudgementKind::*;
+        use PressStrength::*;
+
+        let judgements = [
+            judgement(Grade::Perfect, Single, Press),
+            judgement(Grade::Great, Double, Press),
+            judgement(Grade::Perfect, Double, Release),
+            judgement(Grade::Miss, Single, Press),
+            judgement(Grade::Good, Triple, Press),
+        ];
+
+        let score = Score::reduce(&judgements);
+
+        assert_eq!((score.combo, score.max_combo), (1, 3));
+        assert_eq!(score.count(Grade::Perfect), 2);
+        assert_eq!(score.points, 300 + 420 + 600 + 360);
+        assert!((score.accuracy().raw() - 100. * 5.6 / 9.).abs() < 1e-4);
+        assert_eq!(score.rank(&RankThresholds::default()), Rank::D);
+        assert_eq!(Score::default().rank(&RankThresholds::default()), Rank::SS);
+    }
+
+    #[test]
+    fn interleaved_judgements() {
+        let at = |time: f32, grade, kind| Judgement {
+            object_time: p32(time),
+            ..judgement(grade, PressStrength::Single, kind)
+        };
+
+        let hits = [
+            at(1., Grade::Perfect, JudgementKind::Press),
+            at(3., Grade::Perfect, JudgementKind::Press),
+        ];
+        let holds = [at(2., Grade::Miss, JudgementKind::Release)];
+
+        let scores = [
+            hits.iter().chain(&holds).collect::<Vec<_>>(),
+            holds.iter().chain(&hits).collect::<Vec<_>>(),
+        ]
+        .map(|frame| Score::default().tap_mut(|score| score.judge_frame(frame)));
+
+        assert_ne!(
+            Score::reduce(hits.iter().chain(&holds)).combo,
+            scores[0].combo
+        );
+        assert_eq!(scores[0], scores[1]);
+        assert_eq!((scores[0].combo, scores[0].max_combo), (1, 1));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{tempo::TempoMap, utils::*, SongTime, VisualTime};
+use std::cmp::Ordering;
+
+use bevy::prelude::*;
+use derive_more::Add;
+use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Seconds between a song time and when the player hears it (audio), between a press and when
+/// it is stamped with the song time (input) and between drawing a frame and when it is seen
+/// (visual). Negative offsets are allowed for sources that run early.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Add, Serialize, Deserialize)]
+pub struct LatencyOffsets {
+    pub audio: R32,
+    pub input: R32,
+    pub visual: R32,
+}
+
+/// Global offsets are calibrated per device, chart offsets come with the loaded chart
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Latency {
+    pub global: LatencyOffsets,
+    pub chart: LatencyOffsets,
+}
+
+impl Latency {
+    pub fn offsets(&self) -> LatencyOffsets {
+        self.global + self.chart
+    }
+
+    /// Song time the player heard when pressing an input stamped with `time`
+    pub fn hit_time(&self, time: P32) -> P32 {
+        let LatencyOffsets { audio, input, .. } = self.offsets();
+        p32((time.raw() - audio.raw() - input.raw()).max(0.))
+    }
+
+    /// Song time the player hears when a frame drawn at `time` is seen
+    pub fn visual_time(&self, time: P32) -> P32 {
+        let LatencyOffsets { audio, visual, .. } = self.offsets();
+        p32((time.raw() - audio.raw() + visual.raw()).max(0.))
+    }
+}
+
+pub fn sync_visual_time(
+    time: Res<SongTime>,
+    latency: Res<Latency>,
+    mut visual_time: ResMut<VisualTime>,
+) {
+    **visual_time = latency.visual_time(**time);
+}
+
+/// Median offset of taps stamped in song time from the nearest beat of a metronome following
+/// `tempo_map`. Taps aren't latency corrected so this is the audio and input offsets combined.
+#[rustfmt::skip]
+pub fn tap_offset(tempo_map: &TempoMap, taps: &[P32]) -> Option<R32> {
+    let mut offsets = taps
+        .iter()
+        .map(|tap| r32(tap.raw() - tempo_map.snap(*tap, 1).raw()))
+        .collect::<Vec<_>>();
+
+    offsets.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    match offsets.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((offsets[len / 2 - 1] + offsets[len / 2]) / 2.),
+        len => Some(offsets[len / 2]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_compensation() {
+        let latency = Latency {
+            global: LatencyOffsets {
+                audio: r32(0.05),
+                input: r32(0.02),
+                visual: r32(0.03),
+            },
+            chart: LatencyOffsets {
+                audio: r32(0.01),
+                ..Default::default()
+            },
+        };
+
+        assert!((latency.hit_time(p32(1.)).raw() - 0.92).abs() < 1e-5);
+        assert!((latency.visual_time(p32(1.)).raw() - 0.97).abs() < 1e-5);
+        assert_eq!(latency.hit_time(p32(0.)), p32(0.));
+    }
+
+    #[test]
+    fn tap_calibration() {
+        let tempo_map = TempoMap::default();
+
+        // 120 BPM so beats are every 0.5s, with one tap off by an outlier
+        let taps = [0.54, 1.06, 1.55, 2.2, 2.53].map(p32);
+        let offset = tap_offset(&tempo_map, &taps).unwrap();
+
+        assert!((offset.raw() - 0.05).abs() < 1e-4);
+        assert!((tap_offset(&tempo_map, &taps[..2]).unwrap().raw() - 0.05).abs() < 1e-4);
+        assert_eq!(tap_offset(&tempo_map, &[]), None);
+    }
+}
@@ -1,17 +1,25 @@
-use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+#![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
 
-#[proc_macro_derive(Lerp)]
-pub fn derive_lerp(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
-    let ident = &input.ident;
-    TokenStream::from(quote! {
-        impl Lerp for #ident {
-            type Output = Self;
-            fn lerp(&self, next: &Self, t: T32) -> Self::Output {
-                Self(self.0.lerp(&next.0, t))
-            }
-        }
-    })
-}
+use utils::*;
+
+use bevy::prelude::*;
+use derive_more::From;
+
+pub mod chart;
+pub mod clock;
+pub mod hit;
+pub mod latency;
+pub mod replay;
+pub mod sheet;
+pub mod tempo;
+pub mod utils;
+
+pub const MAX_CHANNELS: usize = 256;
+
+#[derive(Default, Clone, Copy, Deref, DerefMut, From)]
+pub struct SongTime(pub P32);
+
+/// Song time of what is being seen, see [`latency::Latency::visual_time`]
+#[derive(Default, Clone, Copy, Deref, DerefMut, From)]
+pub struct VisualTime(pub P32);
@@ -1,26 +1,15 @@
-#![allow(clippy::type_complexity)]
-#![allow(clippy::too_many_arguments)]
-
-use utils::*;
+use rhythm_engine::{hit::HitInputPlugin, sheet::SheetPlugin};
 
 use bevy::prelude::*;
 use bevy_prototype_lyon::prelude::*;
-use derive_more::From;
-
-mod hit;
-mod sheet;
-mod utils;
-
-pub const MAX_CHANNELS: usize = 256;
-
-#[derive(Clone, Copy, Deref, DerefMut, From)]
-pub struct SongTime(pub P32);
 
 fn main() {
     App::new()
         .insert_resource(Msaa { samples: 4 })
         .add_plugins(DefaultPlugins)
         .add_plugin(ShapePlugin)
+        .add_plugin(SheetPlugin)
+        .add_plugin(HitInputPlugin)
         .add_startup_system(setup_system)
         .run();
 }
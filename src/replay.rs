@@ -0,0 +1,374 @@
+//! Replays are every [`HitInfo`] and [`HitRelease`] registered in a play, grouped by the song
+//! time of the frame they were registered in and saved with bincode. Inserting a [`Recording`]
+//! resource records a play and inserting a [`Playback`] resource replaces the registers with
+//! the replay's. Played back on a fixed step [`SongClock`](crate::clock::SongClock) with the
+//! step it was recorded with, every frame registers exactly what it did in the recorded play.
+
+use crate::{hit::*, utils::*, SongTime};
+
+use bevy::prelude::*;
+use derive_more::{Display, From};
+use serde::{Deserialize, Serialize};
+
+pub const REPLAY_VERSION: u32 = 2;
+
+#[derive(Debug, Display, From)]
+pub enum ReplayError {
+    Bincode(bincode::Error),
+    #[from(ignore)]
+    #[display(
+        fmt = "Unsupported replay version {} (latest is {})",
+        _0,
+        REPLAY_VERSION
+    )]
+    Version(u32),
+    #[from(ignore)]
+    #[display(
+        fmt = "Replay registers layer {}, but layers must be below {}",
+        _0,
+        MAX_LAYERS
+    )]
+    Layer(u8),
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Frames without hits or releases aren't recorded
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub time: P32,
+    pub hits: Vec<HitInfo>,
+    pub releases: Vec<HitRelease>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            frames: vec![],
+        }
+    }
+}
+
+impl Replay {
+    /// Replays of version 1 are migrated to the current version
+    pub fn load(bytes: &[u8]) -> Result<Self, ReplayError> {
+        match bincode::deserialize::<u32>(bytes)? {
+            1 => bincode::deserialize::<v1::Replay>(bytes)?.migrate(),
+            REPLAY_VERSION => Ok(bincode::deserialize(bytes)?),
+            version => Err(ReplayError::Version(version)),
+        }
+    }
+
+    pub fn save(&self) -> Result<Vec<u8>, ReplayError> {
+        Ok(bincode::serialize(self)?)
+    }
+}
+
+/// Hits and releases registered a single layer each
+mod v1 {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HitInfo {
+        pub object_time: P32,
+        pub hit_time: P32,
+        pub layer: u8,
+        pub strength: PressStrength,
+        pub phat: bool,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct HitRelease {
+        pub release_time: P32,
+        pub layer: u8,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ReplayFrame {
+        pub time: P32,
+        pub hits: Vec<HitInfo>,
+        pub releases: Vec<HitRelease>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Replay {
+        pub version: u32,
+        pub frames: Vec<ReplayFrame>,
+    }
+
+    impl Replay {
+        pub fn migrate(self) -> Result<super::Replay, ReplayError> {
+            let mask = |layer| LayerMask::checked(layer).ok_or(ReplayError::Layer(layer));
+
+            #[rustfmt::skip]
+            let frames = self
+                .frames
+                .into_iter()
+                .map(|frame| Ok(super::ReplayFrame {
+                    time: frame.time,
+                    hits: frame
+                        .hits
+                        .into_iter()
+                        .map(|hit| Ok(super::HitInfo {
+                            object_time: hit.object_time,
+                            hit_time: hit.hit_time,
+                            layers: mask(hit.layer)?,
+                            strength: hit.strength,
+                            phat: hit.phat,
+                        }))
+                        .collect::<Result<_, ReplayError>>()?,
+                    releases: frame
+                        .releases
+                        .into_iter()
+                        .map(|release| Ok(super::HitRelease {
+                            release_time: release.release_time,
+                            layers: mask(release.layer)?,
+                        }))
+                        .collect::<Result<_, ReplayError>>()?,
+                }))
+                .collect::<Result<_, ReplayError>>()?;
+
+            Ok(super::Replay {
+                version: REPLAY_VERSION,
+                frames,
+            })
+        }
+    }
+}
+
+#[derive(Default, Deref, DerefMut)]
+pub struct Recording(pub Replay);
+
+pub struct Playback {
+    replay: Replay,
+    next: usize,
+    last: P32,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            next: 0,
+            last: p32(0.),
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Frames due since the last advance. Moving back in time restarts from the first frame
+    /// not before `time`.
+    pub fn advance(&mut self, time: P32) -> &[ReplayFrame] {
+        let frames = &self.replay.frames;
+
+        if time < self.last {
+            self.next = frames.partition_point(|frame| frame.time < time);
+        }
+
+        let start = self.next;
+        self.next += frames[start..]
+            .iter()
+            .take_while(|frame| frame.time <= time)
+            .count();
+        self.last = time;
+
+        &frames[start..self.next]
+    }
+}
+
+pub fn play_back_replay(
+    time: Res<SongTime>,
+    playback: Option<ResMut<Playback>>,
+    mut hits: ResMut<HitRegister>,
+    mut releases: ResMut<ReleaseRegister>,
+) {
+    let mut playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+
+    let frames = playback.advance(**time);
+
+    **hits = frames
+        .iter()
+        .flat_map(|frame| &frame.hits)
+        .copied()
+        .collect();
+    **releases = frames
+        .iter()
+        .flat_map(|frame| &frame.releases)
+        .copied()
+        .collect();
+}
+
+pub fn record_replay(
+    time: Res<SongTime>,
+    hits: Res<HitRegister>,
+    releases: Res<ReleaseRegister>,
+    recording: Option<ResMut<Recording>>,
+) {
+    let mut recording = match recording {
+        Some(recording) if !hits.is_empty() || !releases.is_empty() => recording,
+        _ => return,
+    };
+
+    recording.frames.push(ReplayFrame {
+        time: **time,
+        hits: hits.to_vec(),
+        releases: releases.to_vec(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::SongClock,
+        hit::{
+            tests::{register_next, NextRegisters},
+            PressKind::{Hold, Press},
+        },
+        sheet::{Coverage, Instance, SheetPlugin, SheetPosition},
+    };
+    use bevy::ecs::event::Events;
+    use noisy_float::prelude::*;
+
+    fn play(hits: impl Fn(P32) -> Vec<HitInfo>, playback: Option<Replay>) -> (Replay, Score) {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.1))
+            .init_resource::<Recording>()
+            .init_resource::<NextRegisters>()
+            .add_plugin(SheetPlugin)
+            .add_system_to_stage(CoreStage::PreUpdate, register_next);
+
+        if let Some(replay) = playback {
+            app.insert_resource(Playback::new(replay));
+        }
+
+        [Press(n32(0.5)), Hold(n32(1.), n32(1.5)), Press(n32(2.))]
+            .into_iter()
+            .for_each(|press_kind| {
+                app.world.spawn().insert(HitPrompt {
+                    press_kind,
+                    press_strength: PressStrength::Single,
+                    press_phat_key: false,
+                    signal_layers: LayerMask::layer(0),
+                });
+            });
+
+        let response = app
+            .world
+            .spawn()
+            .insert(HitResponse {
+                kind: ResponseKind::Toggle(1),
+                layers: LayerMask::layer(0),
+                condition: ResponseCondition::Any,
+            })
+            .id();
+
+        app.world
+            .spawn()
+            .insert(SheetPosition {
+                start: p32(0.),
+                duration: p32(3.),
+                coverage: Coverage(0, 0),
+            })
+            .insert(Instance::<HitResponse>::new(response))
+            .insert(ResponseState::Nil);
+
+        app.world.resource_mut::<SongClock>().play();
+
+        (0..25).for_each(|_| {
+            let time = **app.world.resource::<SongTime>();
+            *app.world.resource_mut::<NextRegisters>() = NextRegisters(hits(time), vec![]);
+            app.update();
+            app.world.resource_mut::<Events<Judgement>>().clear();
+        });
+
+        (
+            app.world.remove_resource::<Recording>().unwrap().0,
+            app.world.resource::<Score>().clone(),
+        )
+    }
+
+    #[test]
+    fn deterministic_playback() {
+        let live = |time: P32| {
+            [0.4, 0.9, 1.9]
+                .into_iter()
+                .filter(|at| (time.raw() - at).abs() < 1e-4)
+                .map(|at| HitInfo {
+                    object_time: p32(at),
+                    hit_time: p32(at + 0.05),
+                    layers: LayerMask::layer(0),
+                    strength: PressStrength::Single,
+                    phat: false,
+                })
+                .collect()
+        };
+
+        let (recorded, live_score) = play(live, None);
+        let saved = recorded.save().unwrap();
+        let (replayed, replayed_score) = play(|_| vec![], Some(Replay::load(&saved).unwrap()));
+
+        assert_eq!(recorded.frames.len(), 3);
+        assert_eq!(replayed.save().unwrap(), saved);
+        assert_eq!(replayed_score, live_score);
+        assert!(matches!(
+            Replay::load(
+                &Replay {
+                    version: 0,
+                    frames: vec![]
+                }
+                .save()
+                .unwrap()
+            ),
+            Err(ReplayError::Version(0))
+        ));
+    }
+
+    #[test]
+    fn v1_migration() {
+        let v1 = |layer| {
+            bincode::serialize(&v1::Replay {
+                version: 1,
+                frames: vec![v1::ReplayFrame {
+                    time: p32(0.5),
+                    hits: vec![v1::HitInfo {
+                        object_time: p32(0.5),
+                        hit_time: p32(0.55),
+                        layer,
+                        strength: PressStrength::Double,
+                        phat: true,
+                    }],
+                    releases: vec![v1::HitRelease {
+                        release_time: p32(0.5),
+                        layer,
+                    }],
+                }],
+            })
+            .unwrap()
+        };
+
+        let replay = Replay::load(&v1(3)).unwrap();
+        let frame = &replay.frames[0];
+
+        assert_eq!(replay.version, REPLAY_VERSION);
+        assert_eq!(frame.hits[0].layers, LayerMask::layer(3));
+        assert_eq!(frame.hits[0].strength, PressStrength::Double);
+        assert_eq!(frame.releases[0].layers, LayerMask::layer(3));
+        assert!(matches!(
+            Replay::load(&v1(MAX_LAYERS)),
+            Err(ReplayError::Layer(MAX_LAYERS))
+        ));
+    }
+}
@@ -1,13 +1,18 @@
-mod automation;
-mod bound_sequence;
-mod repeater;
-mod spline;
+pub mod automation;
+pub mod binding;
+pub mod bound_sequence;
+pub mod repeater;
+pub mod spline;
 
-use bound_sequence::*;
-use repeater::*;
-use spline::*;
+pub use binding::*;
+pub use bound_sequence::*;
+pub use repeater::*;
+pub use spline::*;
 
-use crate::{hit::*, utils::*, SongTime, MAX_CHANNELS};
+use crate::{
+    clock::*, hit::*, latency::*, replay::*, tempo::TempoMap, utils::*, SongTime, VisualTime,
+    MAX_CHANNELS,
+};
 use std::{
     marker::PhantomData,
     ops::{Deref, RangeInclusive},
@@ -15,16 +20,17 @@ use std::{
 
 use bevy::{ecs::system::SystemParam, prelude::*};
 use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize};
 use tap::tap::Tap;
 
-#[derive(Clone, Copy)]
-pub struct Coverage(u8, u8);
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Coverage(pub u8, pub u8);
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Serialize, Deserialize)]
 pub struct SheetPosition {
     pub start: P32,
     pub duration: P32,
-    coverage: Coverage,
+    pub coverage: Coverage,
 }
 
 impl SheetPosition {
@@ -32,6 +38,15 @@ impl SheetPosition {
         self.coverage.0.into()..=self.coverage.1.into()
     }
 
+    /// Positions a sheet `beats` long starting at beat `start`
+    pub fn from_beats(tempo_map: &TempoMap, start: P32, beats: P32, coverage: Coverage) -> Self {
+        Self {
+            start: tempo_map.time_at(start),
+            duration: tempo_map.span(start, beats),
+            coverage,
+        }
+    }
+
     pub fn scheduled_at(&self, time: P32) -> bool {
         (self.start.raw()..self.start.raw() + self.duration.raw()).contains(&time.raw())
     }
@@ -59,17 +74,18 @@ impl<T> Deref for Instance<T> {
     }
 }
 
-type Automation = automation::Automation<T32>;
-type Color = BoundSequence<Rgba>;
-type Luminosity = BoundSequence<bound_sequence::Luminosity>;
-type Scale = BoundSequence<bound_sequence::Scale>;
-type Rotation = BoundSequence<bound_sequence::Rotation>;
+pub type Automation = automation::Automation<T32>;
+pub type Color = BoundSequence<Rgba>;
+pub type Luminosity = BoundSequence<bound_sequence::Luminosity>;
+pub type Scale = BoundSequence<bound_sequence::Scale>;
+pub type Rotation = BoundSequence<bound_sequence::Rotation>;
 
 #[rustfmt::skip]
 #[derive(SystemParam)]
 struct Ensemble<'w, 's, T: Component> {
     entities: Query<'w, 's, &'static T>,
     sheets: Query<'w, 's, (
+        Entity,
         &'static SheetPosition,
         &'static Instance<T>,
         &'static RepeaterAffinity,
@@ -80,39 +96,101 @@ struct Ensemble<'w, 's, T: Component> {
 impl<'w, 's, T: Component> Ensemble<'w, 's, T> {
     fn add_all<'a>(
         &'a self,
-        time: SongTime,
+        time: P32,
         arrangements: &mut [Arrangement<'a>],
-        grabber: impl for<'b> Fn(&'b mut Arrangement<'a>) -> &'b mut Option<&'a T>,
+        grabber: impl for<'b> Fn(&'b mut Arrangement<'a>) -> &'b mut Option<(Entity, &'a T)>,
     ) {
         self.sheets
             .iter()
-            .filter(|(pos, ..)| f32::EPSILON < pos.duration.raw())
-            .filter(|(pos, ..)| pos.scheduled_at(*time))
-            .for_each(|(pos, instance, _)| arrangements[pos.coverage()]
+            .filter(|(_, pos, ..)| f32::EPSILON < pos.duration.raw())
+            .filter(|(_, pos, ..)| pos.scheduled_at(time))
+            .for_each(|(sheet, pos, instance, _)| arrangements[pos.coverage()]
                 .iter_mut()
-                .for_each(|arrangement| *grabber(arrangement) = self.entities.get(**instance).ok())
+                .for_each(|arrangement| *grabber(arrangement) = self
+                    .entities
+                    .get(**instance)
+                    .ok()
+                    .map(|content| (sheet, content))
+                )
             )
     }
 }
 
+/// Contents paired with the sheet they are played from
 #[derive(Default)]
 struct Arrangement<'a> {
     /// Exclusive
-    spline: Option<&'a Spline>,
-    automation: Option<&'a Automation>,
+    spline: Option<(Entity, &'a Spline)>,
+    automation: Option<(Entity, &'a Automation)>,
     /// Exclusive
     /// REQ: Some(_) = anchors
-    color: Option<&'a Color>,
-    luminosity: Option<&'a Luminosity>,
-    scale: Option<&'a Scale>,
-    rotation: Option<&'a Rotation>,
+    color: Option<(Entity, &'a Color)>,
+    luminosity: Option<(Entity, &'a Luminosity)>,
+    scale: Option<(Entity, &'a Scale)>,
+    rotation: Option<(Entity, &'a Rotation)>,
     /// Optional
     /// REQ: Some(_) = anchors && Some(_) = (rotation | scale)
-    geometry_ctrl: Option<&'a GeometryCtrl>,
+    geometry_ctrl: Option<(Entity, &'a GeometryCtrl)>,
+}
+
+/// Where automation and bound sequence sheets last played their anchors, for each channel
+/// they cover from the first since each channel can be at a different offset. Bound sequences
+/// use both cursors, automations only the first. Added to sheets by [`add_playback_cursors`].
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct PlaybackCursors(pub Vec<[PlaybackCursor; 2]>);
+
+impl PlaybackCursors {
+    pub fn new(position: &SheetPosition) -> Self {
+        Self(vec![
+            Default::default();
+            position.coverage::<usize>().count()
+        ])
+    }
+}
+
+/// Sheets without cursors for the channel yet seek on every play
+fn with_cursors<R>(
+    cursors: &mut Query<(&SheetPosition, &mut PlaybackCursors)>,
+    sheet: Entity,
+    channel: usize,
+    play: impl FnOnce(&mut [PlaybackCursor; 2]) -> R,
+) -> R {
+    match cursors.get_mut(sheet) {
+        Ok((position, mut cursors)) => match channel
+            .checked_sub(position.coverage.0 as usize)
+            .and_then(|index| cursors.get_mut(index))
+        {
+            Some(cursors) => play(cursors),
+            None => play(&mut Default::default()),
+        },
+        Err(_) => play(&mut Default::default()),
+    }
+}
+
+/// Sized again whenever the coverage of a sheet changes
+fn add_playback_cursors(
+    mut commands: Commands,
+    sheets: Query<
+        (Entity, &SheetPosition),
+        Or<(
+            Added<Instance<Automation>>,
+            Added<Instance<Color>>,
+            Added<Instance<Luminosity>>,
+            Added<Instance<Scale>>,
+            Added<Instance<Rotation>>,
+            (Changed<SheetPosition>, With<PlaybackCursors>),
+        )>,
+    >,
+) {
+    sheets.iter().for_each(|(sheet, position)| {
+        commands
+            .entity(sheet)
+            .insert(PlaybackCursors::new(position));
+    });
 }
 
 #[derive(Default, Clone, Copy)]
-enum Modulation {
+pub enum Modulation {
     #[default]
     Nil,
     Position(Vec2),
@@ -128,10 +206,19 @@ enum Modulation {
     },
 }
 
+#[derive(Deref, DerefMut)]
+pub struct Modulations(pub [Modulation; MAX_CHANNELS]);
+
+impl Default for Modulations {
+    fn default() -> Self {
+        Self([Modulation::Nil; MAX_CHANNELS])
+    }
+}
+
 #[rustfmt::skip]
 fn produce_modulations(
-    time: Res<SongTime>,
     In(sheet_inputs): In<[(ResponseOutput, RepeaterOutput); MAX_CHANNELS]>,
+    time: Res<VisualTime>,
     splines: Ensemble<Spline>,
     automations: Ensemble<Automation>,
     colors: Ensemble<Color>,
@@ -139,18 +226,256 @@ fn produce_modulations(
     scales: Ensemble<Scale>,
     rotations: Ensemble<Rotation>,
     geometry_ctrls: Ensemble<GeometryCtrl>,
+    mut cursors: Query<(&SheetPosition, &mut PlaybackCursors)>,
 )
     -> [Modulation; MAX_CHANNELS]
 {
     let arrangements = [(); MAX_CHANNELS].map(|_| Arrangement::default()).tap_mut(|arrangements| {
-        splines.add_all(*time, arrangements, |arrangement| &mut arrangement.spline);
-        automations.add_all(*time, arrangements, |arrangement| &mut arrangement.automation);
-        colors.add_all(*time, arrangements, |arrangement| &mut arrangement.color);
-        luminosities.add_all(*time, arrangements, |arrangement| &mut arrangement.luminosity);
-        scales.add_all(*time, arrangements, |arrangement| &mut arrangement.scale);
-        rotations.add_all(*time, arrangements, |arrangement| &mut arrangement.rotation);
-        geometry_ctrls.add_all(*time, arrangements, |arrangement| &mut arrangement.geometry_ctrl);
+        splines.add_all(**time, arrangements, |arrangement| &mut arrangement.spline);
+        automations.add_all(**time, arrangements, |arrangement| &mut arrangement.automation);
+        colors.add_all(**time, arrangements, |arrangement| &mut arrangement.color);
+        luminosities.add_all(**time, arrangements, |arrangement| &mut arrangement.luminosity);
+        scales.add_all(**time, arrangements, |arrangement| &mut arrangement.scale);
+        rotations.add_all(**time, arrangements, |arrangement| &mut arrangement.rotation);
+        geometry_ctrls.add_all(**time, arrangements, |arrangement| &mut arrangement.geometry_ctrl);
     });
 
-    todo!()
+    [Modulation::Nil; MAX_CHANNELS].tap_mut(|modulations| modulations
+        .iter_mut()
+        .zip(arrangements.iter().zip(sheet_inputs))
+        .enumerate()
+        .for_each(|(channel, (modulation, (arrangement, (response, repeater))))| {
+            let offset = repeater.repeat_time;
+
+            let t = response
+                .redirect
+                .map_or(arrangement.automation, |index| arrangements[index as usize].automation)
+                .map(|(sheet, automation)| with_cursors(&mut cursors, sheet, channel, |[cursor, _]| {
+                    automation.play_with(cursor, offset)
+                }))
+                .map(|t| repeater.lower_clamp.lerp(&repeater.upper_clamp, t));
+
+            let ctrl = arrangement.geometry_ctrl.map(|(_, ctrl)| **ctrl);
+
+            *modulation = match (arrangement, t) {
+                (Arrangement { spline: Some((_, spline)), .. }, Some(t)) => {
+                    Modulation::Position(spline.play(t))
+                }
+                (Arrangement { color: Some((sheet, color)), .. }, Some(t)) => {
+                    with_cursors(&mut cursors, *sheet, channel, |cursors| {
+                        Modulation::Color(color.play_with(cursors, offset, t))
+                    })
+                }
+                (Arrangement { luminosity: Some((sheet, luminosity)), .. }, Some(t)) => {
+                    with_cursors(&mut cursors, *sheet, channel, |cursors| {
+                        Modulation::Luminosity(*luminosity.play_with(cursors, offset, t))
+                    })
+                }
+                (Arrangement { scale: Some((sheet, scale)), .. }, Some(t)) => {
+                    with_cursors(&mut cursors, *sheet, channel, |cursors| {
+                        Modulation::Scale { magnitude: *scale.play_with(cursors, offset, t), ctrl }
+                    })
+                }
+                (Arrangement { rotation: Some((sheet, rotation)), .. }, Some(t)) => {
+                    with_cursors(&mut cursors, *sheet, channel, |cursors| {
+                        Modulation::Rotation { theta: *rotation.play_with(cursors, offset, t), ctrl }
+                    })
+                }
+                _ => Modulation::Nil,
+            }
+        })
+    )
+}
+
+fn publish_modulations(
+    In(produced): In<[Modulation; MAX_CHANNELS]>,
+    mut modulations: ResMut<Modulations>,
+) {
+    **modulations = produced;
+}
+
+/// Runs after `CoreStage::PreUpdate`, where the `SongClock` updates `SongTime`, so song time and
+/// the `HitRegister` are settled for the frame and the published `Modulations` are ready for
+/// anything in `CoreStage::Update`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct SheetStage;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum SheetLabel {
+    Replay,
+    ClearResponses,
+    TrackHolds,
+    JudgeHits,
+    JudgeHolds,
+    Modulate,
+}
+
+pub struct SheetPlugin;
+
+impl Plugin for SheetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SongTime>()
+            .init_resource::<VisualTime>()
+            .init_resource::<Latency>()
+            .init_resource::<SongClock>()
+            .init_resource::<AudioPosition>()
+            .init_resource::<TempoMap>()
+            .init_resource::<HitRegister>()
+            .init_resource::<ReleaseRegister>()
+            .init_resource::<HeldLayers>()
+            .init_resource::<Modulations>()
+            .init_resource::<TimingWindows>()
+            .init_resource::<Score>()
+            .init_resource::<RankThresholds>()
+            .add_event::<Judgement>()
+            .add_system_to_stage(CoreStage::First, clear_registers)
+            .add_system_to_stage(CoreStage::PreUpdate, tick_song_clock.label(SongClockLabel))
+            .add_system_to_stage(CoreStage::PreUpdate, sync_visual_time.after(SongClockLabel))
+            .add_system_to_stage(CoreStage::PreUpdate, add_playback_cursors)
+            .add_stage_after(CoreStage::PreUpdate, SheetStage, SystemStage::parallel())
+            .add_system_to_stage(
+                SheetStage,
+                clear_hit_responses.label(SheetLabel::ClearResponses),
+            )
+            .add_system_to_stage(SheetStage, play_back_replay.label(SheetLabel::Replay))
+            .add_system_to_stage(SheetStage, autoplay.label(SheetLabel::Replay))
+            .add_system_to_stage(SheetStage, record_replay.after(SheetLabel::Replay))
+            .add_system_to_stage(
+                SheetStage,
+                track_holds
+                    .label(SheetLabel::TrackHolds)
+                    .after(SheetLabel::Replay),
+            )
+            .add_system_to_stage(
+                SheetStage,
+                judge_hits
+                    .label(SheetLabel::JudgeHits)
+                    .after(SheetLabel::Replay),
+            )
+            .add_system_to_stage(
+                SheetStage,
+                judge_holds
+                    .label(SheetLabel::JudgeHolds)
+                    .after(SheetLabel::TrackHolds)
+                    .after(SheetLabel::JudgeHits),
+            )
+            .add_system_to_stage(SheetStage, score_judgements.after(SheetLabel::JudgeHolds))
+            .add_system_to_stage(
+                SheetStage,
+                respond_to_hits
+                    .chain(produce_repetitions)
+                    .chain(produce_modulations)
+                    .chain(publish_modulations)
+                    .label(SheetLabel::Modulate)
+                    .after(SheetLabel::Replay)
+                    .after(SheetLabel::ClearResponses)
+                    .after(SheetLabel::TrackHolds)
+                    .after(SheetLabel::JudgeHolds),
+            )
+            .add_system(capture_rest_transforms)
+            .add_system(apply_transforms)
+            .add_system(apply_colors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hit::tests::{register_next, NextRegisters},
+        sheet::automation::Anchor,
+    };
+
+    #[test]
+    fn cursor_modulations() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.5))
+            .add_plugin(SheetPlugin);
+
+        let automation = Automation::new((0..4).map(|x| Anchor {
+            x: p32(x as f32),
+            val: t32(x as f32 / 3.),
+            weight: automation::Weight::default(),
+            out: None,
+        }))
+        .unwrap();
+
+        let content = app.world.spawn().insert(automation.clone()).id();
+        let sheet = app
+            .world
+            .spawn()
+            .insert_bundle((
+                SheetPosition {
+                    start: p32(0.),
+                    duration: p32(4.),
+                    coverage: Coverage(1, 2),
+                },
+                RepeaterAffinity(false),
+                Instance::<Automation>::new(content),
+            ))
+            .id();
+
+        app.world.resource_mut::<SongClock>().play();
+        (0..6).for_each(|_| app.update());
+
+        let mut expected = PlaybackCursor::default();
+        expected.advance(&automation, **app.world.resource::<VisualTime>());
+
+        let cursors = app.world.get::<PlaybackCursors>(sheet).unwrap();
+        assert_ne!(expected, PlaybackCursor::default());
+        assert_eq!(cursors.len(), 2);
+        assert!(cursors.iter().all(|[cursor, _]| *cursor == expected));
+    }
+
+    #[test]
+    fn registers_clear() {
+        let mut app = App::new();
+        app.insert_resource(SongClock::fixed(0.1))
+            .init_resource::<NextRegisters>()
+            .add_plugin(SheetPlugin)
+            .add_system_to_stage(CoreStage::PreUpdate, register_next);
+
+        let response = app
+            .world
+            .spawn()
+            .insert(HitResponse {
+                kind: ResponseKind::Toggle(1),
+                layers: LayerMask::layer(0),
+                condition: ResponseCondition::Any,
+            })
+            .id();
+
+        let sheet = app
+            .world
+            .spawn()
+            .insert_bundle((
+                SheetPosition {
+                    start: p32(0.),
+                    duration: p32(4.),
+                    coverage: Coverage(0, 0),
+                },
+                Instance::<HitResponse>::new(response),
+                ResponseState::Nil,
+            ))
+            .id();
+
+        let hit = HitInfo {
+            object_time: p32(0.),
+            hit_time: p32(0.),
+            layers: LayerMask::layer(0),
+            strength: PressStrength::Single,
+            phat: false,
+        };
+
+        app.world.resource_mut::<SongClock>().play();
+        app.update();
+        *app.world.resource_mut::<NextRegisters>() = NextRegisters(vec![hit], vec![]);
+        (0..6).for_each(|_| app.update());
+
+        assert!(app.world.resource::<HitRegister>().is_empty());
+        assert!(matches!(
+            app.world.get::<ResponseState>(sheet),
+            Some(ResponseState::Delegated(true))
+        ));
+    }
 }
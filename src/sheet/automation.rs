@@ -1,23 +1,118 @@
-use crate::utils::*;
+use crate::{tempo::TempoMap, utils::*};
+use std::ops::RangeInclusive;
+
 use bevy::prelude::*;
+use derive_more::Display;
 use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize, Serializer};
 use tinyvec::TinyVec;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ease {
+    In,
+    Out,
+    InOut,
+}
+
+impl Ease {
+    /// Shapes an ease in curve. Ends are exact regardless of rounding in the curve.
+    fn apply(&self, f: impl Fn(f32) -> f32, t: f32) -> f32 {
+        match (t, self) {
+            (t, _) if t <= 0. => 0.,
+            (t, _) if 1. <= t => 1.,
+            (t, Ease::In) => f(t),
+            (t, Ease::Out) => 1. - f(1. - t),
+            (t, Ease::InOut) if t < 0.5 => f(2. * t) / 2.,
+            (t, Ease::InOut) => 1. - f(2. - 2. * t) / 2.,
+        }
+    }
+}
+
+fn bounce(t: f32) -> f32 {
+    let (n, d) = (7.5625, 2.75);
+
+    1. - match 1. - t {
+        t if t < 1. / d => n * t * t,
+        t if t < 2. / d => n * (t - 1.5 / d).powi(2) + 0.75,
+        t if t < 2.5 / d => n * (t - 2.25 / d).powi(2) + 0.9375,
+        t => n * (t - 2.625 / d).powi(2) + 0.984375,
+    }
+}
+
+/// Cubic bezier from (0, 0) to (1, 1) as in CSS. Control point x values are clamped to the
+/// unit interval so the curve is a function of x.
+fn bezier([x1, y1]: [R32; 2], [x2, y2]: [R32; 2], t: f32) -> f32 {
+    let (x1, x2) = (x1.raw().clamp(0., 1.), x2.raw().clamp(0., 1.));
+    let at = |a: f32, b: f32, s: f32| {
+        3. * a * s * (1. - s).powi(2) + 3. * b * s * s * (1. - s) + s.powi(3)
+    };
+
+    let (mut lo, mut hi) = (0., 1.);
+    (0..32).for_each(|_| {
+        let mid = (lo + hi) / 2.;
+        match at(x1, x2, mid) < t {
+            true => lo = mid,
+            false => hi = mid,
+        }
+    });
+
+    at(y1.raw(), y2.raw(), (lo + hi) / 2.)
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum Weight {
     Constant,
     Quadratic(R32),
     Cubic(R32),
+    Sine(Ease),
+    Exponential(Ease),
+    Circular(Ease),
+    /// Overshoots past the start or end
+    Back(Ease),
+    /// Overshoots past the start or end
+    Elastic(Ease),
+    Bounce(Ease),
+    /// Holds for `1 / n` of the way before each step. 0 steps holds like 1.
+    Step(u8),
+    /// Control points of a cubic bezier. Overshoots where they are outside the unit interval.
+    Bezier([R32; 2], [R32; 2]),
 }
 
 impl Weight {
-    pub fn eval(&self, t: T32) -> T32 {
+    /// Can leave the unit interval for weights that overshoot, see [`Lerp::extrapolate`]
+    pub fn curve(&self, t: T32) -> R32 {
+        use std::f32::consts::PI;
         let f = |x: f32, k: f32| x.signum() * x.abs().powf((k + k.signum()).abs().powf(k.signum()));
+        let (c1, t) = (1.70158, t.raw());
 
-        match self {
-            Weight::Constant => t32(1.),
-            Weight::Quadratic(k) => t32(f(t.raw(), k.raw())),
-            Weight::Cubic(k) => t32(((f(2. * t.raw() - 1., k.raw()) - 1.) / 2.) + 1.),
-        }
+        r32(match self {
+            Weight::Constant => 1.,
+            Weight::Quadratic(k) => f(t, k.raw()),
+            Weight::Cubic(k) => ((f(2. * t - 1., k.raw()) - 1.) / 2.) + 1.,
+            Weight::Sine(ease) => ease.apply(|t| 1. - (t * PI / 2.).cos(), t),
+            Weight::Exponential(ease) => ease.apply(|t| 2f32.powf(10. * t - 10.), t),
+            Weight::Circular(ease) => ease.apply(|t| 1. - (1. - t * t).max(0.).sqrt(), t),
+            Weight::Back(ease) => ease.apply(|t| (c1 + 1.) * t.powi(3) - c1 * t * t, t),
+            Weight::Elastic(ease) => ease.apply(
+                |t| -(2f32.powf(10. * t - 10.)) * ((10. * t - 10.75) * 2. * PI / 3.).sin(),
+                t,
+            ),
+            Weight::Bounce(ease) => ease.apply(bounce, t),
+            Weight::Step(n) => {
+                let n = (*n).max(1) as f32;
+                (t * n).floor() / n
+            }
+            Weight::Bezier(p1, p2) => match t {
+                t if t <= 0. => 0.,
+                t if 1. <= t => 1.,
+                t => bezier(*p1, *p2, t),
+            },
+        })
+    }
+
+    /// [`Weight::curve`] clamped to the unit interval
+    pub fn eval(&self, t: T32) -> T32 {
+        t32(self.curve(t).raw().clamp(0., 1.))
     }
 }
 
@@ -27,11 +122,45 @@ impl Default for Weight {
     }
 }
 
-#[derive(Default)]
+/// Without an `out` weight the segment leaving an anchor is eased by the `weight` of the next
+/// anchor alone, as charts from before `out` weights were added expect. With one the segment
+/// blends from `out` to the `weight` of the next anchor across its whole length, so it leaves
+/// with the slope of `out` and arrives with the slope of `weight`.
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Anchor<T> {
-    x: P32,
-    val: T,
-    weight: Weight,
+    pub x: P32,
+    pub val: T,
+    /// Easing into this anchor
+    pub weight: Weight,
+    /// Easing out of this anchor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out: Option<Weight>,
+}
+
+impl<T> Anchor<T> {
+    /// Anchors `beats` beats into a sheet that starts at beat `start`
+    pub fn from_beats(
+        tempo_map: &TempoMap,
+        start: P32,
+        beats: P32,
+        val: T,
+        weight: Weight,
+    ) -> Self {
+        Self {
+            x: tempo_map.span(start, beats),
+            val,
+            weight,
+            out: None,
+        }
+    }
+
+    /// Weight of the segment to `next`. Continuous as long as both weights keep their ends.
+    pub fn curve(&self, next: &Self, t: T32) -> R32 {
+        match self.out {
+            None => next.weight.curve(t),
+            Some(out) => out.curve(t).lerp(&next.weight.curve(t), t),
+        }
+    }
 }
 
 impl<T> Quantify for Anchor<T> {
@@ -46,20 +175,167 @@ where
 {
     type Output = <T as Lerp>::Output;
     fn lerp(&self, next: &Self, t: T32) -> Self::Output {
-        next.val.lerp(&self.val, next.weight.eval(t))
+        self.val.extrapolate(&next.val, self.curve(next, t))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum AutomationError {
+    #[display(fmt = "Automations must have at least one anchor")]
+    Empty,
+    #[display(fmt = "No anchor at index {}", _0)]
+    Index(usize),
+}
+
+impl std::error::Error for AutomationError {}
+
+/// Anchors are kept sorted by `x` and there is always at least one, as
+/// [`ControlTable`] expects. Anchors at the same `x` keep the order they were added in.
+#[derive(Deref, Component, Clone, Deserialize)]
+#[serde(try_from = "Vec<Anchor<T>>")]
+pub struct Automation<T: Default>(TinyVec<[Anchor<T>; 6]>);
+
+impl<T: Default> TryFrom<Vec<Anchor<T>>> for Automation<T> {
+    type Error = AutomationError;
+
+    fn try_from(anchors: Vec<Anchor<T>>) -> Result<Self, Self::Error> {
+        Self::new(anchors)
     }
 }
 
-#[derive(Deref, DerefMut, Component)]
-pub struct Automation<T: Default>(pub TinyVec<[Anchor<T>; 6]>);
+impl<T: Default + Serialize> Serialize for Automation<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.as_slice().serialize(serializer)
+    }
+}
+
+impl<T: Default> Automation<T> {
+    /// Sorts the anchors by `x`
+    pub fn new(anchors: impl IntoIterator<Item = Anchor<T>>) -> Result<Self, AutomationError> {
+        let mut anchors = anchors.into_iter().collect::<TinyVec<[Anchor<T>; 6]>>();
+        anchors.sort_by_key(|anchor| anchor.x);
+
+        match anchors.is_empty() {
+            true => Err(AutomationError::Empty),
+            false => Ok(Self(anchors)),
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Result<&mut Anchor<T>, AutomationError> {
+        self.0.get_mut(index).ok_or(AutomationError::Index(index))
+    }
+
+    /// Index of the first anchor after `x`
+    fn upper_bound(&self, x: P32) -> usize {
+        self.0.partition_point(|anchor| anchor.x <= x)
+    }
+
+    /// Index of the first anchor at or after `x`
+    fn lower_bound(&self, x: P32) -> usize {
+        self.0.partition_point(|anchor| anchor.x < x)
+    }
+
+    /// Goes after any anchors at the same `x`. Returns the index it was inserted at.
+    pub fn insert_anchor(&mut self, anchor: Anchor<T>) -> usize {
+        let index = self.upper_bound(anchor.x);
+        self.0.insert(index, anchor);
+        index
+    }
+
+    /// Returns the index the anchor was moved to
+    pub fn move_anchor(&mut self, index: usize, x: P32) -> Result<usize, AutomationError> {
+        (index < self.len())
+            .then_some(())
+            .ok_or(AutomationError::Index(index))?;
+        let anchor = self.0.remove(index);
+        Ok(self.insert_anchor(Anchor { x, ..anchor }))
+    }
+
+    pub fn remove_anchor(&mut self, index: usize) -> Result<Anchor<T>, AutomationError> {
+        (index < self.len())
+            .then_some(())
+            .ok_or(AutomationError::Index(index))?;
+
+        match self.0.len() {
+            1 => Err(AutomationError::Empty),
+            _ => Ok(self.0.remove(index)),
+        }
+    }
+
+    pub fn set_value(&mut self, index: usize, val: T) -> Result<(), AutomationError> {
+        self.get_mut(index).map(|anchor| anchor.val = val)
+    }
+
+    pub fn set_weight(&mut self, index: usize, weight: Weight) -> Result<(), AutomationError> {
+        self.get_mut(index).map(|anchor| anchor.weight = weight)
+    }
+
+    pub fn set_out(&mut self, index: usize, out: Option<Weight>) -> Result<(), AutomationError> {
+        self.get_mut(index).map(|anchor| anchor.out = out)
+    }
+
+    /// Anchors with `x` in `range`
+    pub fn anchors_in(&self, range: RangeInclusive<P32>) -> &[Anchor<T>] {
+        let (start, end) = (
+            self.lower_bound(*range.start()),
+            self.upper_bound(*range.end()),
+        );
+        &self.0[start..end.max(start)]
+    }
+
+    /// Removes the anchors with `x` in `range`. Nothing is removed if that would remove every
+    /// anchor.
+    pub fn remove_range(
+        &mut self,
+        range: RangeInclusive<P32>,
+    ) -> Result<Vec<Anchor<T>>, AutomationError> {
+        let (start, end) = (
+            self.lower_bound(*range.start()),
+            self.upper_bound(*range.end()),
+        );
+
+        match (start, end) {
+            (start, end) if end <= start => Ok(vec![]),
+            (0, end) if self.0.len() <= end => Err(AutomationError::Empty),
+            (start, end) => Ok(self.0.drain(start..end).collect()),
+        }
+    }
+
+    /// Moves the anchors with `x` in `range` by `by`, stopping at 0. Moved anchors go after
+    /// anchors they land on.
+    pub fn shift_range(&mut self, range: RangeInclusive<P32>, by: R32) {
+        let (start, end) = (
+            self.lower_bound(*range.start()),
+            self.upper_bound(*range.end()),
+        );
+
+        if start < end {
+            let moved = self.0.drain(start..end).collect::<Vec<_>>();
+
+            moved.into_iter().for_each(|anchor| {
+                self.insert_anchor(Anchor {
+                    x: p32((anchor.x.raw() + by.raw()).max(0.)),
+                    ..anchor
+                });
+            });
+        }
+    }
+}
 
 impl<T> Automation<T>
 where
-    T: Default + Copy + Quantify + Lerp<Output = T>,
+    T: Default + Copy + Lerp<Output = T>,
 {
     pub fn play(&self, offset: P32) -> T {
         self.interp(offset).unwrap_or_else(|anchor| anchor.val)
     }
+
+    /// Same as [`Automation::play`] but only looks ahead of where `cursor` last played
+    pub fn play_with(&self, cursor: &mut PlaybackCursor, offset: P32) -> T {
+        cursor
+            .interp(self, offset)
+            .unwrap_or_else(|anchor| anchor.val)
+    }
 }
 
 #[cfg(test)]
@@ -98,6 +374,354 @@ mod tests {
         })
     }
 
+    #[test]
+    #[rustfmt::skip]
+    fn automation_play() {
+        let automation = Automation::<T32>(
+            [(0., 0., Constant), (1., 1., Quadratic(r32(0.))), (2., 0., Constant)]
+                .into_iter()
+                .map(|(x, val, weight)| Anchor {
+                    x: p32(x),
+                    val: t32(val),
+                    weight,
+                    out: None,
+                })
+                .collect(),
+        );
+
+        [(0., 0.), (0.5, 0.5), (0.75, 0.75), (1.5, 0.), (3., 0.)]
+            .into_iter()
+            .for_each(|(offset, val)| assert_eq!(automation.play(p32(offset)), t32(val)));
+    }
+
+    fn eases() -> impl Iterator<Item = Weight> {
+        [Ease::In, Ease::Out, Ease::InOut]
+            .into_iter()
+            .flat_map(|ease| {
+                [Sine, Exponential, Circular, Back, Elastic, Bounce].map(|weight| weight(ease))
+            })
+    }
+
+    fn beziers() -> impl Iterator<Item = Weight> {
+        [
+            ([0., 0.], [1., 1.]),
+            ([0.25, 0.1], [0.25, 1.]),
+            ([0.42, 0.], [1., 1.]),
+            ([0.68, -0.6], [0.32, 1.6]),
+        ]
+        .into_iter()
+        .map(|(p1, p2)| Bezier(p1.map(r32), p2.map(r32)))
+    }
+
+    #[test]
+    fn easing_inflections() {
+        eases()
+            .chain(beziers())
+            .chain((0..8).map(Step))
+            .for_each(|weight| {
+                assert_eq!(weight.curve(t32(0.)), r32(0.));
+                assert_eq!(weight.curve(t32(1.)), r32(1.));
+            });
+
+        [Sine, Exponential, Circular, Back, Elastic, Bounce]
+            .map(|weight| weight(Ease::InOut))
+            .into_iter()
+            .for_each(|weight| assert!((weight.curve(t32(0.5)).raw() - 0.5).abs() < 1e-5));
+
+        let linear = Bezier([r32(0.), r32(0.)], [r32(1.), r32(1.)]);
+        let css_ease = Bezier([r32(0.25), r32(0.1)], [r32(0.25), r32(1.)]);
+
+        (0..=100).map(|i| t32(i as f32 / 100.)).for_each(|t| {
+            assert!((linear.curve(t).raw() - t.raw()).abs() < 1e-4);
+        });
+        assert!((css_ease.curve(t32(0.5)).raw() - 0.8024).abs() < 1e-3);
+
+        assert_eq!(Step(4).curve(t32(0.3)), r32(0.25));
+        assert_eq!(Step(1).curve(t32(0.99)), r32(0.));
+        assert_eq!(Step(0).curve(t32(0.99)), r32(0.));
+    }
+
+    #[test]
+    fn easing_overshoot() {
+        assert!(Back(Ease::In).curve(t32(0.2)) < 0.);
+        assert!(r32(1.) < Back(Ease::Out).curve(t32(0.8)));
+        assert!(r32(1.) < Elastic(Ease::Out).curve(t32(0.1)));
+        assert_eq!(Elastic(Ease::Out).eval(t32(0.1)), t32(1.));
+
+        let automation = Automation::<R32>(
+            [(0., 0., Constant), (1., 10., Back(Ease::Out))]
+                .into_iter()
+                .map(|(x, val, weight)| Anchor {
+                    x: p32(x),
+                    val: r32(val),
+                    weight,
+                    out: None,
+                })
+                .collect(),
+        );
+
+        assert!(r32(10.) < automation.play(p32(0.8)));
+        assert_eq!(automation.play(p32(1.)), r32(10.));
+
+        let clamped = Automation::<T32>(
+            [(0., 0., Constant), (1., 1., Back(Ease::Out))]
+                .into_iter()
+                .map(|(x, val, weight)| Anchor {
+                    x: p32(x),
+                    val: t32(val),
+                    weight,
+                    out: None,
+                })
+                .collect(),
+        );
+
+        assert_eq!(clamped.play(p32(0.8)), t32(1.));
+    }
+
+    #[test]
+    fn anchor_out_weights() {
+        let anchor = |x, val, weight, out| Anchor {
+            x: p32(x),
+            val: r32(val),
+            weight,
+            out,
+        };
+
+        let legacy = Automation::<R32>(
+            [
+                anchor(0., 0., Constant, None),
+                anchor(1., 1., Sine(Ease::InOut), None),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let same = Automation::<R32>(
+            [
+                anchor(0., 0., Constant, Some(Sine(Ease::InOut))),
+                anchor(1., 1., Sine(Ease::InOut), None),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let mixed = Automation::<R32>(
+            [
+                anchor(0., 0., Constant, Some(Back(Ease::In))),
+                anchor(1., 1., Sine(Ease::In), None),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        (0..=100).map(|i| p32(i as f32 / 100.)).for_each(|offset| {
+            assert!((legacy.play(offset) - same.play(offset)).abs() < 1e-5);
+        });
+
+        assert!(mixed.play(p32(0.2)) < r32(0.));
+        assert_eq!(mixed.play(p32(0.)), r32(0.));
+        assert_eq!(mixed.play(p32(1.)), r32(1.));
+
+        (1..=100).map(|i| i as f32 / 100.).for_each(|offset| {
+            let step = mixed.play(p32(offset)) - mixed.play(p32(offset - 0.01));
+            assert!(step.abs() < 0.1);
+        });
+    }
+
+    #[test]
+    fn anchor_out_slopes() {
+        let anchor = |x, val, weight, out| Anchor {
+            x: p32(x),
+            val: r32(val),
+            weight,
+            out,
+        };
+
+        let slopes = |out| {
+            let automation = Automation::<R32>(
+                [
+                    anchor(0., 0., Constant, None),
+                    anchor(1., 1., Sine(Ease::In), out),
+                    anchor(2., 2., Sine(Ease::In), None),
+                ]
+                .into_iter()
+                .collect(),
+            );
+
+            let h = 1e-3;
+            let at = |x: f32| automation.play(p32(x)).raw();
+            ((at(1.) - at(1. - h)) / h, (at(1. + h) - at(1.)) / h)
+        };
+
+        let (arriving, leaving) = slopes(Some(Sine(Ease::Out)));
+        assert!((arriving - leaving).abs() < 0.01);
+
+        let (arriving, leaving) = slopes(None);
+        assert!(0.5 < (arriving - leaving).abs());
+    }
+
+    fn anchor(x: f32, val: f32) -> Anchor<R32> {
+        Anchor {
+            x: p32(x),
+            val: r32(val),
+            weight: Weight::default(),
+            out: None,
+        }
+    }
+
+    fn xs(automation: &Automation<R32>) -> Vec<f32> {
+        automation.iter().map(|anchor| anchor.x.raw()).collect()
+    }
+
+    #[test]
+    fn anchor_sorting() {
+        assert_eq!(
+            Automation::<R32>::new([]).err(),
+            Some(AutomationError::Empty)
+        );
+
+        let automation = Automation::new([anchor(2., 2.), anchor(0., 0.)]).unwrap();
+        assert_eq!(xs(&automation), [0., 2.]);
+
+        assert!(ron::from_str::<Automation<R32>>("[]").is_err());
+        let loaded = ron::from_str::<Automation<R32>>(
+            "[(x: 1.0, val: 1.0, weight: Constant), (x: 0.0, val: 0.0, weight: Constant)]",
+        )
+        .unwrap();
+        assert_eq!(xs(&loaded), [0., 1.]);
+    }
+
+    #[test]
+    fn anchor_insertion() {
+        let mut automation = Automation::new([anchor(0., 0.), anchor(2., 2.)]).unwrap();
+
+        assert_eq!(automation.insert_anchor(anchor(1., 1.)), 1);
+        assert_eq!(automation.insert_anchor(anchor(1., 3.)), 2);
+        assert_eq!(automation[2].val, r32(3.));
+        assert_eq!(xs(&automation), [0., 1., 1., 2.]);
+    }
+
+    #[test]
+    fn anchor_moving() {
+        let mut automation =
+            Automation::new([anchor(0., 0.), anchor(1., 1.), anchor(2., 2.)]).unwrap();
+
+        assert_eq!(automation.move_anchor(0, p32(1.)), Ok(1));
+        assert_eq!(automation[1].val, r32(0.));
+        assert_eq!(xs(&automation), [1., 1., 2.]);
+        assert_eq!(
+            automation.move_anchor(3, p32(0.)),
+            Err(AutomationError::Index(3))
+        );
+    }
+
+    #[test]
+    fn anchor_setting() {
+        let mut automation = Automation::new([anchor(0., 0.), anchor(1., 1.)]).unwrap();
+
+        assert_eq!(automation.set_value(1, r32(2.)), Ok(()));
+        assert_eq!(automation.set_weight(1, Constant), Ok(()));
+        assert_eq!(automation.set_out(0, Some(Constant)), Ok(()));
+        assert_eq!(automation.play(p32(0.5)), r32(2.));
+        assert_eq!(
+            automation.set_value(2, r32(0.)),
+            Err(AutomationError::Index(2))
+        );
+        assert_eq!(
+            automation.set_weight(2, Constant),
+            Err(AutomationError::Index(2))
+        );
+        assert_eq!(automation.set_out(2, None), Err(AutomationError::Index(2)));
+    }
+
+    #[test]
+    fn anchor_removal() {
+        let mut automation = Automation::new([anchor(0., 0.), anchor(1., 1.)]).unwrap();
+
+        assert_eq!(
+            automation.remove_anchor(2).err(),
+            Some(AutomationError::Index(2))
+        );
+        assert_eq!(
+            automation.remove_anchor(0).map(|anchor| anchor.val),
+            Ok(r32(0.))
+        );
+        assert_eq!(
+            automation.remove_anchor(0).err(),
+            Some(AutomationError::Empty)
+        );
+    }
+
+    #[test]
+    fn anchors_in_range() {
+        let automation = Automation::new([
+            anchor(1., 1.),
+            anchor(1., 1.),
+            anchor(1.5, 1.5),
+            anchor(2., 2.),
+        ])
+        .unwrap();
+
+        assert_eq!(automation.anchors_in(p32(1.)..=p32(1.5)).len(), 3);
+        assert!(automation.anchors_in(p32(1.6)..=p32(1.9)).is_empty());
+    }
+
+    #[test]
+    fn range_shifting() {
+        let mut automation = Automation::new([
+            anchor(1., 1.),
+            anchor(1., 1.),
+            anchor(1.5, 1.5),
+            anchor(2., 2.),
+        ])
+        .unwrap();
+
+        automation.shift_range(p32(1.)..=p32(1.), r32(-2.));
+        assert_eq!(xs(&automation), [0., 0., 1.5, 2.]);
+    }
+
+    #[test]
+    fn range_removal() {
+        let mut automation =
+            Automation::new([anchor(0., 0.), anchor(1.5, 1.5), anchor(2., 2.)]).unwrap();
+
+        assert_eq!(
+            automation.remove_range(p32(0.)..=p32(3.)).err(),
+            Some(AutomationError::Empty)
+        );
+        assert!(automation
+            .remove_range(p32(0.5)..=p32(1.))
+            .unwrap()
+            .is_empty());
+        assert_eq!(automation.remove_range(p32(1.)..=p32(3.)).unwrap().len(), 2);
+        assert_eq!(xs(&automation), [0.]);
+    }
+
+    #[test]
+    fn cursor_playback() {
+        let automation = Automation::<R32>::new((0..1000).map(|i| Anchor {
+            x: p32((i / 2) as f32),
+            val: r32((i % 7) as f32),
+            weight: Weight::default(),
+            out: None,
+        }))
+        .unwrap();
+
+        let mut cursor = PlaybackCursor::default();
+
+        (0..2000)
+            .map(|i| i as f32 / 4.)
+            .chain([100., 20., 20.5, 700., 0.])
+            .chain((0..100).map(|i| 600. + i as f32 * 0.3))
+            .map(p32)
+            .for_each(|offset| {
+                assert_eq!(
+                    automation.play_with(&mut cursor, offset),
+                    automation.play(offset)
+                );
+            });
+    }
+
     #[test]
     fn weight_growth() {
         (-20..=20).map(|i| i as f32).map(r32).for_each(|weight| {
@@ -106,6 +730,18 @@ mod tests {
                 assert!(Quadratic(weight).eval(t0) < Quadratic(weight).eval(t1));
                 assert!(Cubic(weight).eval(t0) <= Cubic(weight).eval(t1));
             })
-        })
+        });
+
+        [Ease::In, Ease::Out, Ease::InOut]
+            .into_iter()
+            .flat_map(|ease| [Sine(ease), Exponential(ease), Circular(ease)])
+            .chain(beziers().take(3))
+            .chain((0..8).map(Step))
+            .for_each(|weight| {
+                (1..=100).map(|i| t32((i as f32) / 100.)).for_each(|t1| {
+                    let t0 = t1 - 0.01;
+                    assert!(weight.curve(t0) <= weight.curve(t1));
+                })
+            });
     }
 }
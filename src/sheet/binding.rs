@@ -0,0 +1,232 @@
+use super::{bound_sequence::Rgba, Modulation, Modulations};
+use crate::utils::*;
+
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+
+/// Binds an entity to the modulation of a channel
+#[derive(Component, Clone, Copy, Deref, DerefMut)]
+pub struct ChannelBinding(pub u8);
+
+/// Transform of a bound entity before any modulation. Positions are offsets from it and
+/// scaling/rotation without a ctrl point happen about it. Bound entities go back to it while
+/// their channel isn't modulated.
+#[derive(Component, Clone, Copy, Deref, DerefMut)]
+pub struct RestTransform(pub Transform);
+
+pub fn capture_rest_transforms(
+    mut commands: Commands,
+    bound: Query<(Entity, &Transform), Added<ChannelBinding>>,
+) {
+    bound.for_each(|(entity, transform)| {
+        commands.entity(entity).insert(RestTransform(*transform));
+    })
+}
+
+#[rustfmt::skip]
+pub fn apply_transforms(
+    modulations: Res<Modulations>,
+    mut bound: Query<(&ChannelBinding, &RestTransform, &mut Transform)>,
+) {
+    bound.for_each_mut(|(binding, rest, mut transform)| {
+        let origin = rest.translation.truncate();
+
+        *transform = match modulations[**binding as usize] {
+            Modulation::Position(offset) => Transform {
+                translation: (origin + offset).extend(rest.translation.z),
+                ..**rest
+            },
+            Modulation::Scale { magnitude, ctrl } => {
+                let pivot = ctrl.unwrap_or(origin);
+                Transform {
+                    translation: (pivot + (origin - pivot) * magnitude.raw())
+                        .extend(rest.translation.z),
+                    scale: rest.scale * magnitude.raw(),
+                    ..**rest
+                }
+            }
+            Modulation::Rotation { theta, ctrl } => Transform {
+                translation: ctrl
+                    .unwrap_or(origin)
+                    .rotate(&origin, theta)
+                    .extend(rest.translation.z),
+                rotation: rest.rotation * Quat::from_rotation_z(theta.raw().to_radians()),
+                ..**rest
+            },
+            Modulation::Nil => **rest,
+            _ => return,
+        }
+    })
+}
+
+fn with_lightness(color: Color, lightness: T32) -> Color {
+    match color.as_hsla() {
+        Color::Hsla {
+            hue,
+            saturation,
+            alpha,
+            ..
+        } => Color::hsla(hue, saturation, lightness.raw(), alpha),
+        _ => unreachable!(),
+    }
+}
+
+impl From<Rgba> for Color {
+    fn from(rgba: Rgba) -> Self {
+        let [r, g, b, a] = rgba.map(|channel| channel.raw());
+        Color::rgba(r, g, b, a)
+    }
+}
+
+/// Colors go to the fill, or the stroke of stroke only shapes.
+/// Luminosity sets the lightness of both the fill and the outline.
+pub fn apply_colors(
+    modulations: Res<Modulations>,
+    mut bound: Query<(&ChannelBinding, &mut DrawMode)>,
+) {
+    bound.for_each_mut(|(binding, mut draw_mode)| {
+        match (modulations[**binding as usize], &mut *draw_mode) {
+            (
+                Modulation::Color(rgba),
+                DrawMode::Fill(FillMode { color, .. })
+                | DrawMode::Stroke(StrokeMode { color, .. })
+                | DrawMode::Outlined {
+                    fill_mode: FillMode { color, .. },
+                    ..
+                },
+            ) => *color = rgba.into(),
+            (
+                Modulation::Luminosity(lightness),
+                DrawMode::Fill(FillMode { color, .. }) | DrawMode::Stroke(StrokeMode { color, .. }),
+            ) => *color = with_lightness(*color, lightness),
+            (
+                Modulation::Luminosity(lightness),
+                DrawMode::Outlined {
+                    fill_mode,
+                    outline_mode,
+                },
+            ) => {
+                fill_mode.color = with_lightness(fill_mode.color, lightness);
+                outline_mode.color = with_lightness(outline_mode.color, lightness);
+            }
+            _ => {}
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noisy_float::prelude::*;
+
+    #[test]
+    fn pivoted_transforms() {
+        let mut world = World::new();
+        let mut stage = SystemStage::single(apply_transforms);
+        let rest = Transform::from_xyz(100., 0., 1.);
+
+        let entity = world
+            .spawn()
+            .insert_bundle((ChannelBinding(0), RestTransform(rest), rest))
+            .id();
+
+        [
+            (
+                Modulation::Position(Vec2::new(0., 50.)),
+                Vec3::new(100., 50., 1.),
+            ),
+            (
+                Modulation::Scale {
+                    magnitude: r32(2.),
+                    ctrl: None,
+                },
+                Vec3::new(100., 0., 1.),
+            ),
+            (
+                Modulation::Scale {
+                    magnitude: r32(2.),
+                    ctrl: Some(Vec2::ZERO),
+                },
+                Vec3::new(200., 0., 1.),
+            ),
+            (
+                Modulation::Rotation {
+                    theta: r32(180.),
+                    ctrl: Some(Vec2::ZERO),
+                },
+                Vec3::new(-100., 0., 1.),
+            ),
+            (Modulation::Nil, Vec3::new(100., 0., 1.)),
+        ]
+        .into_iter()
+        .for_each(|(modulation, translation)| {
+            let mut modulations = Modulations::default();
+            modulations[0] = modulation;
+            world.insert_resource(modulations);
+            stage.run(&mut world);
+
+            let transform = world.get::<Transform>(entity).unwrap();
+            assert!(transform.translation.abs_diff_eq(translation, 1e-3));
+        });
+
+        assert_eq!(world.get::<Transform>(entity).unwrap().scale, rest.scale);
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().rotation,
+            rest.rotation
+        );
+    }
+
+    #[test]
+    fn modulated_colors() {
+        let mut world = World::new();
+        let mut stage = SystemStage::single(apply_colors);
+        let fill = FillMode::color(Color::RED);
+
+        let [filled, outlined] = [
+            DrawMode::Fill(fill),
+            DrawMode::Outlined {
+                fill_mode: fill,
+                outline_mode: StrokeMode::new(Color::BLUE, 1.),
+            },
+        ]
+        .map(|draw_mode| {
+            world
+                .spawn()
+                .insert_bundle((ChannelBinding(0), draw_mode))
+                .id()
+        });
+
+        let colors = |world: &World| {
+            [filled, outlined].map(|entity| match world.get::<DrawMode>(entity).unwrap() {
+                DrawMode::Fill(fill_mode) => (fill_mode.color, None),
+                DrawMode::Outlined {
+                    fill_mode,
+                    outline_mode,
+                } => (fill_mode.color, Some(outline_mode.color)),
+                DrawMode::Stroke(_) => unreachable!(),
+            })
+        };
+
+        let mut modulations = Modulations::default();
+        modulations[0] = Modulation::Color(Rgba([0., 1., 0., 1.].map(t32)));
+        world.insert_resource(modulations);
+        stage.run(&mut world);
+
+        let green = Color::rgba(0., 1., 0., 1.);
+        assert_eq!(colors(&world), [(green, None), (green, Some(Color::BLUE))]);
+
+        let mut modulations = Modulations::default();
+        modulations[0] = Modulation::Luminosity(t32(1.));
+        world.insert_resource(modulations);
+        stage.run(&mut world);
+
+        colors(&world)
+            .into_iter()
+            .flat_map(|(fill, outline)| [Some(fill), outline])
+            .flatten()
+            .for_each(|color| match color.as_hsla() {
+                Color::Hsla { lightness, .. } => assert!((lightness - 1.).abs() < 1e-3),
+                _ => unreachable!(),
+            });
+    }
+}
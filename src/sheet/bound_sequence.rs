@@ -1,23 +1,29 @@
 use bevy::prelude::*;
 use derive_more::{Deref, DerefMut};
 use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use macros::*;
 
 use super::automation::*;
 use crate::utils::*;
 
-#[derive(Default, Clone, Copy, Deref, DerefMut, Lerp)]
-pub struct Scale(R32);
-#[derive(Default, Clone, Copy, Deref, DerefMut, Lerp)]
-pub struct Rotation(R32);
-#[derive(Component)]
-pub struct GeometryCtrl(Vec2);
+#[derive(Default, Clone, Copy, Deref, DerefMut, Lerp, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Scale(pub R32);
+#[derive(Default, Clone, Copy, Deref, DerefMut, Lerp, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Rotation(pub R32);
+#[derive(Component, Clone, Copy, Deref, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GeometryCtrl(pub Vec2);
 
-#[derive(Component, Default, Clone, Copy, Deref, DerefMut, Lerp)]
-pub struct Luminosity(T32);
-#[derive(Component, Default, Clone, Copy, Deref, DerefMut)]
-pub struct Rgba([T32; 4]);
+#[derive(Component, Default, Clone, Copy, Deref, DerefMut, Lerp, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Luminosity(pub T32);
+#[derive(Component, Default, Clone, Copy, Deref, DerefMut, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Rgba(pub [T32; 4]);
 
 impl Lerp for Rgba {
     type Output = Self;
@@ -29,21 +35,37 @@ impl Lerp for Rgba {
 
         Rgba([(); 4].map(|_| iter.next().unwrap()))
     }
+
+    fn extrapolate(&self, other: &Self, t: R32) -> Self::Output {
+        let mut iter = self
+            .iter()
+            .zip(other.iter())
+            .map(|(from, to)| from.extrapolate(to, t));
+
+        Rgba([(); 4].map(|_| iter.next().unwrap()))
+    }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct BoundSequence<T: Default> {
-    upper: Automation<T>,
-    lower: Automation<T>,
+    pub upper: Automation<T>,
+    pub lower: Automation<T>,
 }
 
 impl<T: Default> BoundSequence<T>
 where
-    T: Default + Copy + Quantify + Lerp + Lerp<Output = T>,
+    T: Default + Copy + Lerp<Output = T>,
 {
     pub fn play(&self, offset: P32, t: T32) -> T {
         self.lower.play(offset).lerp(&self.upper.play(offset), t)
     }
+
+    /// Plays the bounds with a cursor each, lower first
+    pub fn play_with(&self, [lower, upper]: &mut [PlaybackCursor; 2], offset: P32, t: T32) -> T {
+        self.lower
+            .play_with(lower, offset)
+            .lerp(&self.upper.play_with(upper, offset), t)
+    }
 }
 
 /*#[cfg(test)]
@@ -1,38 +1,60 @@
 use super::automation::Weight;
-use crate::{hit::*, sheet::*, utils::*};
+use crate::{hit::*, sheet::*, tempo::TempoMap, utils::*};
 
 use bevy::prelude::*;
 use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize};
 use tap::tap::Tap;
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct RepeaterClamp {
-    start: T32,
-    end: T32,
-    weight: Weight,
+    pub start: T32,
+    pub end: T32,
+    pub weight: Weight,
 }
 
 impl RepeaterClamp {
     pub fn eval(&self, t: T32) -> T32 {
-        self.start.lerp(&self.end, self.weight.eval(t))
+        self.start.extrapolate(&self.end, self.weight.curve(t))
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Serialize, Deserialize)]
 pub struct Repeater {
-    ping_pong: bool,
-    period: P32,
-    ceil: RepeaterClamp,
-    floor: RepeaterClamp,
+    pub ping_pong: bool,
+    pub period: P32,
+    pub ceil: RepeaterClamp,
+    pub floor: RepeaterClamp,
 }
 
-#[derive(Component)]
-pub struct RepeaterAffinity(bool);
+impl Repeater {
+    /// Repeats every `beats` beats of a sheet that starts at beat `start`
+    pub fn from_beats(
+        tempo_map: &TempoMap,
+        start: P32,
+        beats: P32,
+        ping_pong: bool,
+        ceil: RepeaterClamp,
+        floor: RepeaterClamp,
+    ) -> Self {
+        Self {
+            ping_pong,
+            period: tempo_map.span(start, beats),
+            ceil,
+            floor,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RepeaterAffinity(pub bool);
 
 #[derive(Clone, Copy)]
 pub struct RepeaterOutput {
-    repeat_time: P32,
-    lower_clamp: T32,
-    upper_clamp: T32,
+    pub repeat_time: P32,
+    pub lower_clamp: T32,
+    pub upper_clamp: T32,
 }
 
 impl RepeaterOutput {
@@ -46,9 +68,9 @@ impl RepeaterOutput {
 }
 
 #[rustfmt::skip]
-fn produce_repetitions(
-    time: Res<SongTime>,
+pub fn produce_repetitions(
     In(response_outputs): In<[ResponseOutput; 256]>,
+    time: Res<VisualTime>,
     repeaters: Query<&Repeater>,
     sheets: Query<(
         &SheetPosition,
@@ -65,7 +87,7 @@ fn produce_repetitions(
             .map(|(pos, instance)| (pos, repeaters.get(**instance).unwrap()))
             .filter(|(_, Repeater { period, .. })| f32::EPSILON < period.raw())
             .for_each(|(pos, Repeater { ping_pong, period, floor, ceil })| {
-                (&mut outputs[pos.coverage()])
+                outputs[pos.coverage()]
                     .iter_mut()
                     .filter(|(response_output, _)| pos.scheduled_at(response_output.seek_time))
                     .for_each(|(ResponseOutput { seek_time, .. }, repeater_output)| {
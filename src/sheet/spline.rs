@@ -5,10 +5,11 @@ use bevy::prelude::*;
 use itertools::Itertools;
 use lyon_geom::*;
 use noisy_float::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use super::automation::*;
 use crate::utils::*;
 
+#[derive(Clone, Copy)]
 pub enum Sample {
     Point {
         displacement: P32,
@@ -17,6 +18,7 @@ pub enum Sample {
     Arc {
         displacement: P32,
         center: Vec2,
+        theta: R32,
     },
 }
 
@@ -36,18 +38,15 @@ impl Lerp for Sample {
             (Sample::Point { position: start, .. }, Sample::Point { position: end, .. }) => {
                 start.lerp(*end, t.raw())
             },
-            (Sample::Point { position: start, .. }, Sample::Arc { center, displacement }) => {
-                center.rotate(
-                    start,
-                    r32((*displacement / center.distance(*start)).to_degrees().raw())
-                )
+            (Sample::Point { position: start, .. }, Sample::Arc { center, theta, .. }) => {
+                center.rotate(start, *theta * t.raw())
             }
             _ => unreachable!()
         }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum Curvature {
     Linear,
     Circular(Vec2),
@@ -55,9 +54,10 @@ pub enum Curvature {
     Cubic(Vec2, Vec2),
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Segment {
-    curvature: Curvature,
-    position: Vec2,
+    pub curvature: Curvature,
+    pub position: Vec2,
 }
 
 #[rustfmt::skip]
@@ -122,23 +122,30 @@ impl Segment {
 
                     let (a, b) = (center - start, center - end);
 
+                    let ctrl_orientation = [start, ctrl, end].into_iter().orientation();
+
                     let theta = match (
-                        [start, ctrl, end].into_iter().orientation(),
+                        &ctrl_orientation,
                         [start, center, end].into_iter().orientation(),
                         (a.dot(b) / (a.length() * b.length())).acos().to_degrees()
                     ) {
-                        (ctrl_o, center_o, theta) if ctrl_o != center_o => theta,
+                        (ctrl_o, center_o, theta) if *ctrl_o != center_o => theta,
                         (_, _, theta) => theta.signum() * (360. - theta.abs()),
                     };
 
-                    let displacement = p32(
-                        2. * PI * ((theta * center.distance(start)).abs() / 360.)
-                    );
+                    let theta = match ctrl_orientation {
+                        Orientation::ClockWise => -theta.abs(),
+                        _ => theta.abs(),
+                    };
+
+                    *path_length += 2. * PI * ((theta * center.distance(start)).abs() / 360.);
+                    let displacement = *path_length;
 
                     let samples = [
                         (f32::EPSILON <= center.distance(start)).then(|| Sample::Arc {
                             center,
                             displacement,
+                            theta: r32(theta),
                         }),
                         Some(Sample::Point {
                             position: end,
@@ -180,14 +187,26 @@ impl Segment {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Serialize, Deserialize)]
 pub struct Spline {
     pub path: Vec<Segment>,
+    #[serde(skip)]
     pub lut: Vec<Sample>,
-    pub automation: Vec<Anchor<R32>>,
 }
 
 impl Spline {
+    /// Samples the path at `t` of its total length
+    #[rustfmt::skip]
+    pub fn play(&self, t: T32) -> Vec2 {
+        self.lut.last().map_or(Vec2::ZERO, |last| self.lut
+            .interp(last.quantify() * t.raw())
+            .unwrap_or_else(|sample| match sample {
+                Sample::Point { position, .. } => *position,
+                Sample::Arc { center, .. } => *center,
+            })
+        )
+    }
+
     #[rustfmt::skip]
     pub fn resample(&mut self) {
         let head = Segment { curvature: Curvature::Linear, position: Vec2::new(0., 0.) };
@@ -207,4 +226,27 @@ impl Spline {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    #[rustfmt::skip]
+    fn spline_play() {
+        let mut spline = Spline {
+            path: [Vec2::new(0., 100.), Vec2::new(100., 100.)]
+                .into_iter()
+                .map(|position| Segment {
+                    curvature: Curvature::Linear,
+                    position,
+                })
+                .collect(),
+            lut: vec![],
+        };
+
+        assert_eq!(spline.play(t32(0.5)), Vec2::ZERO);
+
+        spline.resample();
+
+        [(0., (0., 0.)), (0.25, (0., 50.)), (0.5, (0., 100.)), (1., (100., 100.))]
+            .into_iter()
+            .for_each(|(t, (x, y))| assert_eq!(spline.play(t32(t)), Vec2::new(x, y)));
+    }
 }
@@ -0,0 +1,309 @@
+use crate::utils::*;
+
+use serde::{Deserialize, Serialize};
+
+pub const TICKS_PER_BEAT: u32 = 480;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignature {
+    pub beats: u8,
+    pub value: u8,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self { beats: 4, value: 4 }
+    }
+}
+
+/// Every timing point starts a new measure at its offset
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TimingPoint {
+    pub offset: P32,
+    pub bpm: P32,
+    #[serde(default)]
+    pub signature: TimeSignature,
+}
+
+impl Quantify for TimingPoint {
+    fn quantify(&self) -> P32 {
+        self.offset
+    }
+}
+
+impl TimingPoint {
+    /// Has a positive BPM and at least one beat per measure
+    fn is_valid(&self) -> bool {
+        f32::EPSILON < self.bpm.raw() && 0 < self.signature.beats
+    }
+
+    fn beat_length(&self) -> P32 {
+        p32(60.) / self.bpm
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BeatPosition {
+    pub measure: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+/// Beat 0 and measure 0 are at the offset of the first timing point.
+/// Song time before it is clamped to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Vec<TimingPoint>", into = "Vec<TimingPoint>")]
+pub struct TempoMap {
+    points: Vec<TimingPoint>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self {
+            points: vec![TimingPoint {
+                offset: p32(0.),
+                bpm: p32(120.),
+                signature: TimeSignature::default(),
+            }],
+        }
+    }
+}
+
+impl TryFrom<Vec<TimingPoint>> for TempoMap {
+    type Error = &'static str;
+
+    fn try_from(points: Vec<TimingPoint>) -> Result<Self, Self::Error> {
+        match points.iter().all(TimingPoint::is_valid) {
+            true => Ok(Self::new(points)),
+            false => {
+                Err("Timing points must have a positive BPM and at least one beat per measure")
+            }
+        }
+    }
+}
+
+impl From<TempoMap> for Vec<TimingPoint> {
+    fn from(tempo_map: TempoMap) -> Self {
+        tempo_map.points
+    }
+}
+
+/// Timing point with the beat and measure it starts on
+struct Section<'a> {
+    point: &'a TimingPoint,
+    beat: P32,
+    measure: u32,
+}
+
+impl TempoMap {
+    /// Falls back to the default tempo map when there are no timing points
+    pub fn new(mut points: Vec<TimingPoint>) -> Self {
+        assert!(
+            points.iter().all(TimingPoint::is_valid),
+            "Timing points must have a positive BPM and at least one beat per measure"
+        );
+
+        points.sort_by_key(|point| point.offset);
+
+        match points.is_empty() {
+            true => Self::default(),
+            false => Self { points },
+        }
+    }
+
+    pub fn points(&self) -> &[TimingPoint] {
+        &self.points
+    }
+
+    fn sections(&self) -> impl Iterator<Item = Section<'_>> {
+        let first = &self.points[0];
+
+        self.points
+            .iter()
+            .scan((first, p32(0.), 0), |(prev, beat, measure), point| {
+                let beats = (point.offset - prev.offset) / prev.beat_length();
+                let measures = (beats.raw() / prev.signature.beats as f32 - 1e-4)
+                    .ceil()
+                    .max(0.);
+
+                *beat += beats;
+                *measure += measures as u32;
+                *prev = point;
+
+                Some(Section {
+                    point,
+                    beat: *beat,
+                    measure: *measure,
+                })
+            })
+    }
+
+    fn section_at(&self, time: P32) -> Section<'_> {
+        let index = self.points.as_slice().seek(time);
+        self.sections().nth(index).unwrap()
+    }
+
+    fn section_at_beat(&self, beat: P32) -> Section<'_> {
+        self.sections()
+            .take_while(|section| section.beat <= beat)
+            .last()
+            .unwrap_or_else(|| self.sections().next().unwrap())
+    }
+
+    pub fn beat_at(&self, time: P32) -> P32 {
+        let Section { point, beat, .. } = self.section_at(time);
+        beat + p32((time.raw() - point.offset.raw()).max(0.)) / point.beat_length()
+    }
+
+    pub fn time_at(&self, beat: P32) -> P32 {
+        let section = self.section_at_beat(beat);
+        section.point.offset + (beat - section.beat) * section.point.beat_length()
+    }
+
+    /// Seconds spanned by `beats` beats starting from `from` beats
+    pub fn span(&self, from: P32, beats: P32) -> P32 {
+        self.time_at(from + beats) - self.time_at(from)
+    }
+
+    pub fn position_at(&self, time: P32) -> BeatPosition {
+        let section = self.section_at(time);
+        let beats = (self.beat_at(time) - section.beat).raw();
+        let per_measure = section.point.signature.beats as f32;
+        let ticks = (beats.fract() * TICKS_PER_BEAT as f32).round() as u32;
+        let (beats, ticks) = match ticks {
+            TICKS_PER_BEAT => (beats.floor() + 1., 0),
+            ticks => (beats.floor(), ticks),
+        };
+
+        BeatPosition {
+            measure: section.measure + (beats / per_measure).floor() as u32,
+            beat: (beats % per_measure) as u32,
+            tick: ticks,
+        }
+    }
+
+    pub fn time_of(&self, position: BeatPosition) -> P32 {
+        let section = self
+            .sections()
+            .take_while(|section| section.measure <= position.measure)
+            .last()
+            .unwrap();
+
+        let point = section.point;
+        let measures = (position.measure - section.measure) as f32;
+        let beats = measures * point.signature.beats as f32
+            + position.beat as f32
+            + position.tick as f32 / TICKS_PER_BEAT as f32;
+
+        point.offset + point.beat_length() * beats
+    }
+
+    /// Snaps to the nearest `1 / divisions` of a beat
+    pub fn snap(&self, time: P32, divisions: u32) -> P32 {
+        let divisions = divisions.max(1) as f32;
+        let beat = self.beat_at(time);
+        self.time_at(p32((beat.raw() * divisions).round() / divisions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempo_map() -> TempoMap {
+        TempoMap::new(vec![
+            TimingPoint {
+                offset: p32(8.),
+                bpm: p32(60.),
+                signature: TimeSignature { beats: 3, value: 4 },
+            },
+            TimingPoint {
+                offset: p32(2.),
+                bpm: p32(120.),
+                signature: TimeSignature::default(),
+            },
+        ])
+    }
+
+    #[test]
+    fn beat_conversions() {
+        let tempo_map = tempo_map();
+
+        [(0., 0.), (2., 0.), (3., 2.), (8., 12.), (10., 14.)]
+            .into_iter()
+            .map(|(time, beat)| (p32(time), p32(beat)))
+            .for_each(|(time, beat)| {
+                assert_eq!(tempo_map.beat_at(time), beat);
+                assert_eq!(tempo_map.time_at(beat), time.max(p32(2.)));
+            });
+
+        assert_eq!(tempo_map.span(p32(10.), p32(4.)), p32(3.));
+    }
+
+    #[test]
+    fn measure_positions() {
+        let tempo_map = tempo_map();
+
+        [
+            (2., (0, 0, 0)),
+            (2.75, (0, 1, 240)),
+            (4., (1, 0, 0)),
+            (7.5, (2, 3, 0)),
+            (8., (3, 0, 0)),
+            (12., (4, 1, 0)),
+        ]
+        .into_iter()
+        .map(|(time, (measure, beat, tick))| {
+            (
+                p32(time),
+                BeatPosition {
+                    measure,
+                    beat,
+                    tick,
+                },
+            )
+        })
+        .for_each(|(time, position)| {
+            assert_eq!(tempo_map.position_at(time), position);
+            assert_eq!(tempo_map.time_of(position), time);
+        });
+
+        assert_eq!(tempo_map.snap(p32(2.3), 4), p32(2.25));
+        assert_eq!(tempo_map.snap(p32(9.4), 2), p32(9.5));
+    }
+
+    #[test]
+    fn invalid_points() {
+        [(0., 4), (120., 0)].into_iter().for_each(|(bpm, beats)| {
+            let points = vec![TimingPoint {
+                offset: p32(0.),
+                bpm: p32(bpm),
+                signature: TimeSignature { beats, value: 4 },
+            }];
+
+            assert!(TempoMap::try_from(points.clone()).is_err());
+            assert!(std::panic::catch_unwind(|| TempoMap::new(points)).is_err());
+        });
+    }
+
+    #[test]
+    fn beat_constructors() {
+        use crate::sheet::{automation::*, *};
+        use noisy_float::prelude::*;
+
+        let tempo_map = tempo_map();
+        let clamp = RepeaterClamp {
+            start: t32(0.),
+            end: t32(1.),
+            weight: Weight::Constant,
+        };
+
+        let position = SheetPosition::from_beats(&tempo_map, p32(10.), p32(4.), Coverage(0, 0));
+        let anchor = Anchor::from_beats(&tempo_map, p32(10.), p32(3.), r32(1.), Weight::Constant);
+        let repeater = Repeater::from_beats(&tempo_map, p32(10.), p32(4.), false, clamp, clamp);
+
+        assert_eq!((position.start, position.duration), (p32(7.), p32(3.)));
+        assert_eq!(anchor.x, p32(2.));
+        assert_eq!(repeater.period, p32(3.));
+    }
+}
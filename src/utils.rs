@@ -53,6 +53,11 @@ pub trait Quantify {
 pub trait Lerp {
     type Output;
     fn lerp(&self, next: &Self, t: T32) -> Self::Output;
+
+    /// For weights that overshoot the unit interval. Clamps `t` unless overridden.
+    fn extrapolate(&self, next: &Self, t: R32) -> Self::Output {
+        self.lerp(next, t32(t.raw().clamp(0., 1.)))
+    }
 }
 
 pub trait FloatExt {
@@ -80,6 +85,10 @@ impl Lerp for R32 {
     fn lerp(&self, other: &Self, t: T32) -> Self::Output {
         *self + (*other - *self) * t.raw()
     }
+
+    fn extrapolate(&self, other: &Self, t: R32) -> Self::Output {
+        *self + (*other - *self) * t
+    }
 }
 
 impl Lerp for T32 {
@@ -87,6 +96,11 @@ impl Lerp for T32 {
     fn lerp(&self, other: &Self, t: T32) -> Self::Output {
         *self + (other.raw() - self.raw()) * t.raw()
     }
+
+    /// Clamped to the unit interval
+    fn extrapolate(&self, other: &Self, t: R32) -> Self::Output {
+        t32((self.raw() + (other.raw() - self.raw()) * t.raw()).clamp(0., 1.))
+    }
 }
 
 impl FloatExt for P32 {
@@ -124,18 +138,21 @@ pub trait ControlTable<'a, T> {
     fn interp(self, offset: P32) -> Result<<T as Lerp>::Output, &'a T>
     where
         T: Lerp;
+    /// `index` must be what [`ControlTable::seek`] returns for `offset`
+    fn interp_from(self, index: usize, offset: P32) -> Result<<T as Lerp>::Output, &'a T>
+    where
+        T: Lerp;
 }
 
 /// Must be non-empty and sorted
 impl<'a, T: Quantify> ControlTable<'a, T> for &'a [T] {
+    /// Last of the items at or before `to`, or the first item if there are none
     fn seek(self, to: impl Quantify) -> usize {
-        let index = self
-            .binary_search_by(|item| item.quantify().cmp(&to.quantify()))
-            .unwrap_or_else(|index| match index {
-                0 => 0,
-                index if self.len() <= index => self.len() - 1,
-                _ => index - 1,
-            });
+        let index = match self.binary_search_by(|item| item.quantify().cmp(&to.quantify())) {
+            Ok(index) => index,
+            Err(0) => return 0,
+            Err(index) => index.min(self.len()) - 1,
+        };
 
         let found = &self[index];
 
@@ -149,22 +166,25 @@ impl<'a, T: Quantify> ControlTable<'a, T> for &'a [T] {
     }
 
     fn can_skip_reindex(self, offset: P32) -> bool {
-        self.last().map_or(true, |item| item.quantify() < offset)
+        self.last().is_none_or(|item| item.quantify() < offset)
     }
 
+    /// Looks a few items ahead of `old` before falling back to a seek. Agrees with
+    /// [`ControlTable::seek`] as long as `old` is at or before where `offset` is.
     #[rustfmt::skip]
     fn reindex_through(self, offset: P32, old: usize) -> usize {
         self.iter()
             .enumerate()
             .skip(old)
             .coalesce(|prev, curr| (prev.1.quantify() == curr.1.quantify())
-                .then(|| curr)
+                .then_some(curr)
                 .ok_or((prev, curr))
             )
-            .take(4)
-            .take_while(|(_, item)| item.quantify() < offset)
-            .last()
-            .map(|(index, _)| index)
+            .take(5)
+            .tuple_windows::<(_, _)>()
+            .find(|(_, (_, next))| offset < next.quantify())
+            .filter(|((_, curr), _)| curr.quantify() <= offset)
+            .map(|((index, _), _)| index)
             .unwrap_or_else(|| self.seek(offset))
     }
 
@@ -172,12 +192,15 @@ impl<'a, T: Quantify> ControlTable<'a, T> for &'a [T] {
     where
         T: Lerp,
     {
-        let start = self
-            .iter()
-            .take_while(|item| offset < item.quantify())
-            .count();
+        self.interp_from(self.seek(offset), offset)
+    }
 
-        match &self[start..] {
+    fn interp_from(self, index: usize, offset: P32) -> Result<<T as Lerp>::Output, &'a T>
+    where
+        T: Lerp,
+    {
+        match &self[index..] {
+            [prev, ..] if offset < prev.quantify() => Err(prev),
             [prev, curr, ..] => {
                 Ok(prev.lerp(curr, offset.unit_interval(prev.quantify(), curr.quantify())))
             }
@@ -186,6 +209,39 @@ impl<'a, T: Quantify> ControlTable<'a, T> for &'a [T] {
     }
 }
 
+/// Remembers where in a [`ControlTable`] the last lookup landed so moving forwards through it
+/// only looks at the next few items. Moving backwards seeks.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PlaybackCursor {
+    index: usize,
+    offset: Option<P32>,
+}
+
+impl PlaybackCursor {
+    /// Same as [`ControlTable::seek`] for `offset`
+    pub fn advance<T: Quantify>(&mut self, table: &[T], offset: P32) -> usize {
+        self.index = match self.offset {
+            Some(last) if last <= offset && self.index < table.len() => {
+                match table.can_skip_reindex(offset) {
+                    true => table.len() - 1,
+                    false => table.reindex_through(offset, self.index),
+                }
+            }
+            _ => table.seek(offset),
+        };
+
+        self.offset = Some(offset);
+        self.index
+    }
+
+    pub fn interp<'a, T>(&mut self, table: &'a [T], offset: P32) -> Result<T::Output, &'a T>
+    where
+        T: Quantify + Lerp,
+    {
+        table.interp_from(self.advance(table, offset), offset)
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub enum Orientation {
     CounterClockWise,
@@ -203,7 +259,7 @@ pub trait OrientationExt: Iterator<Item = Vec2> + Clone {
             .sum::<f32>()
         {
             sum if sum < 0. => Orientation::CounterClockWise,
-            sum if sum == 0. => Orientation::CoLinear,
+            0. => Orientation::CoLinear,
             sum if 0. < sum => Orientation::ClockWise,
             _ => unreachable!(),
         }
@@ -211,3 +267,133 @@ pub trait OrientationExt: Iterator<Item = Vec2> + Clone {
 }
 
 impl<T: Iterator<Item = Vec2> + Clone> OrientationExt for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Key {
+        x: P32,
+        val: R32,
+    }
+
+    impl Quantify for Key {
+        fn quantify(&self) -> P32 {
+            self.x
+        }
+    }
+
+    impl Lerp for Key {
+        type Output = R32;
+        fn lerp(&self, next: &Self, t: T32) -> Self::Output {
+            self.val.lerp(&next.val, t)
+        }
+    }
+
+    /// Gaps of 0 make duplicate timestamps
+    fn table() -> impl Strategy<Value = Vec<Key>> {
+        prop::collection::vec((0..4u8, -100..100i32), 1..40).prop_map(|keys| {
+            keys.into_iter()
+                .scan(0., |x, (gap, val)| {
+                    *x += gap as f32 / 2.;
+                    Some(Key {
+                        x: p32(*x),
+                        val: r32(val as f32),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn play(table: &[Key], offset: f32) -> f32 {
+        table
+            .interp(p32(offset))
+            .map_or_else(|key| key.val.raw(), |val| val.raw())
+    }
+
+    proptest! {
+        #[test]
+        fn reindex_agrees_with_seek(
+            table in table(),
+            offsets in prop::collection::vec(0f32..25., 1..20)
+        ) {
+            let mut offsets = offsets.into_iter().map(p32).collect::<Vec<_>>();
+            offsets.sort();
+
+            offsets.iter().tuple_windows::<(_, _)>().for_each(|(from, to)| {
+                let old = table.seek(*from);
+                assert_eq!(table.as_slice().reindex_through(*to, old), table.seek(*to));
+            });
+        }
+
+        #[test]
+        fn cursor_agrees_with_seek(
+            table in table(),
+            offsets in prop::collection::vec(0f32..25., 1..40)
+        ) {
+            let mut cursor = PlaybackCursor::default();
+
+            offsets.into_iter().map(p32).for_each(|offset| {
+                assert_eq!(cursor.advance(&table, offset), table.seek(offset));
+            });
+        }
+
+        #[test]
+        fn interp_bounded(table in table(), offset in 0f32..25.) {
+            let index = table.seek(p32(offset));
+            let val = play(&table, offset);
+
+            match (&table[index..], table[0].x.raw()) {
+                (_, first) if offset < first => assert_eq!(val, table[0].val.raw()),
+                ([prev, next, ..], _) => {
+                    let (lo, hi) = (prev.val.min(next.val).raw(), prev.val.max(next.val).raw());
+                    assert!(lo <= val && val <= hi, "{val} outside {lo}..={hi}");
+                }
+                _ => assert_eq!(val, table.last().unwrap().val.raw()),
+            }
+        }
+
+        #[test]
+        fn interp_continuous(table in table(), offset in 0f32..25.) {
+            let epsilon = 1e-3;
+            let jumps = table
+                .iter()
+                .tuple_windows::<(_, _)>()
+                .any(|(prev, next)| prev.x == next.x
+                    && (offset..=offset + epsilon).contains(&next.x.raw())
+                );
+
+            // Gaps are at least 0.5 and values at most 200 apart
+            if !jumps {
+                let change = (play(&table, offset + epsilon) - play(&table, offset)).abs();
+                assert!(change <= 400. * epsilon + 1e-3, "{change} over {epsilon}");
+            }
+        }
+
+        #[test]
+        fn duplicates_jump(table in table()) {
+            table
+                .iter()
+                .enumerate()
+                .tuple_windows::<(_, _)>()
+                .filter(|((_, prev), (_, next))| prev.x == next.x)
+                .for_each(|((index, key), _)| {
+                    let first = table[..=index]
+                        .iter()
+                        .rev()
+                        .take_while(|prev| prev.x == key.x)
+                        .last()
+                        .unwrap();
+                    let last = table[table.seek(key.x)];
+                    assert_eq!(play(&table, key.x.raw()), last.val.raw());
+
+                    if 1e-3 <= key.x.raw() {
+                        let before = play(&table, key.x.raw() - 1e-3);
+                        assert!((before - first.val.raw()).abs() <= 0.4 + 1e-3);
+                    }
+                });
+        }
+    }
+}